@@ -0,0 +1,194 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::prelude::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::error::Error;
+use crate::util::{unix_now, write_atomically};
+use super::role::Role;
+use super::credentials::Identity;
+
+use std::fs::read_to_string;
+use std::collections::{BTreeSet, HashMap};
+
+
+/// Default location of the API key store, relative to the working directory of the server
+pub const API_KEYS_PATH: &str = ".api_keys";
+
+const KEY_PREFIX: &str = "iot";
+
+/// Long-lived keys for dashboards and scripts. A key looks like `iot_<id>_<secret>`, only the SHA-256 hash of the secret
+/// is stored. The secret carries 256 bits of entropy, so a fast hash is sufficient here, unlike for passwords.
+///
+/// The file holds one `id role expires_at devices hash name` entry per line. `expires_at` is a unix timestamp in seconds
+/// and `devices` a comma separated list of device IDs, both may be `-`.
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    keys: HashMap<String, ApiKey>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub role: Role,
+    pub devices: BTreeSet<i32>,
+    /// Unix timestamp in seconds, `None` if the key never expires
+    pub expires_at: Option<u64>,
+    #[serde(skip)]
+    hash: [u8; 32]
+}
+
+impl ApiKey {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= unix_now())
+    }
+}
+
+impl ApiKeys {
+    /// Loads the store from `path`. Empty lines and lines starting with `#` are ignored.
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let file: String = read_to_string(path).map_err(|err| Error::ApiKeyStoreError(format!("{path}: {err}")))?;
+
+        let mut keys: HashMap<String, ApiKey> = HashMap::new();
+
+        for (line_number, line) in file.lines().enumerate() {
+            let line: &str = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let key: ApiKey = parse_line(line).ok_or_else(|| Error::ApiKeyStoreError(format!("{path}:{}: expected `id role expires_at devices hash name`", line_number + 1)))?;
+            keys.insert(key.id.clone(), key);
+        }
+
+        Ok(Self { keys })
+    }
+
+    /// Loads the store from `path`, or returns an empty store if the file doesn't exist yet
+    pub fn load_or_default(path: &str) -> Result<Self, Error> {
+        if std::path::Path::new(path).exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        let mut ids: Vec<&String> = self.keys.keys().collect();
+        ids.sort();
+
+        let file: String = ids.into_iter().map(|id| {
+            let ApiKey { name, role, devices, expires_at, hash, .. } = &self.keys[id];
+
+            let expires_at: String = expires_at.map_or("-".to_string(), |expires_at| expires_at.to_string());
+            let devices: String = if devices.is_empty() {
+                "-".to_string()
+            } else {
+                devices.iter().map(i32::to_string).collect::<Vec<String>>().join(",")
+            };
+
+            format!("{id} {role} {expires_at} {devices} {} {name}\n", hex(hash))
+        }).collect();
+
+        write_atomically(path, &file).map_err(|err| Error::ApiKeyStoreError(format!("{path}: {err}")))
+    }
+
+    /// Creates a new key and returns its metadata together with the plaintext key. The plaintext is not stored and
+    /// can't be recovered later.
+    pub fn issue(&mut self, name: &str, role: Role, devices: BTreeSet<i32>, expires_at: Option<u64>) -> (ApiKey, String) {
+        let id: String = hex(&random_bytes::<6>());
+        let secret: String = BASE64_URL_SAFE_NO_PAD.encode(random_bytes::<32>());
+
+        let key: ApiKey = ApiKey {
+            id: id.clone(),
+            name: name.split_whitespace().collect::<Vec<&str>>().join(" "),
+            role,
+            devices,
+            expires_at,
+            hash: Sha256::digest(secret.as_bytes()).into()
+        };
+
+        self.keys.insert(id.clone(), key.clone());
+
+        (key, format!("{KEY_PREFIX}_{id}_{secret}"))
+    }
+
+    /// Returns false if no key with this id exists
+    pub fn revoke(&mut self, id: &str) -> bool {
+        self.keys.remove(id).is_some()
+    }
+
+    pub fn list(&self) -> Vec<ApiKey> {
+        let mut keys: Vec<ApiKey> = self.keys.values().cloned().collect();
+        keys.sort_by(|a, b| a.id.cmp(&b.id));
+        keys
+    }
+
+    /// Returns the identity bound to `key`, or `None` if the key is unknown, revoked or expired.
+    /// The hash comparison is constant-time.
+    pub fn verify(&self, key: &str) -> Option<Identity> {
+        let mut parts = key.splitn(3, '_');
+
+        let (Some(KEY_PREFIX), Some(id), Some(secret)) = (parts.next(), parts.next(), parts.next()) else {
+            return None;
+        };
+
+        let stored: &ApiKey = self.keys.get(id)?;
+        let hash: [u8; 32] = Sha256::digest(secret.as_bytes()).into();
+
+        if bool::from(stored.hash.ct_eq(&hash)) && !stored.is_expired() {
+            Some(Identity { username: format!("api-key:{id}"), role: stored.role, devices: stored.devices.clone() })
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Option<ApiKey> {
+    let mut fields = line.splitn(6, ' ');
+
+    let id: String = fields.next()?.to_string();
+    let role: Role = fields.next()?.parse().ok()?;
+
+    let expires_at: Option<u64> = match fields.next()? {
+        "-" => None,
+        expires_at => Some(expires_at.parse().ok()?)
+    };
+
+    let devices: BTreeSet<i32> = match fields.next()? {
+        "-" => BTreeSet::new(),
+        devices => devices.split(',').map(|device_id| device_id.parse().ok()).collect::<Option<_>>()?
+    };
+
+    let hash: [u8; 32] = unhex(fields.next()?)?;
+    let name: String = fields.next().unwrap_or_default().to_string();
+
+    Some(ApiKey { id, name, role, devices, expires_at, hash })
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes: [u8; N] = [0; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut bytes: [u8; 32] = [0; 32];
+
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * index..2 * index + 2], 16).ok()?;
+    }
+
+    Some(bytes)
+}
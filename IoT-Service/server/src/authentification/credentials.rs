@@ -0,0 +1,167 @@
+use argon2::Argon2;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+
+use crate::error::Error;
+use crate::util::write_atomically;
+use super::role::Role;
+
+use std::fs::read_to_string;
+use std::sync::LazyLock;
+use std::collections::{BTreeSet, HashMap};
+
+
+/// Default location of the credential store, relative to the working directory of the server
+pub const CREDENTIALS_PATH: &str = ".credentials";
+
+/// Hash used to verify passwords of unknown users, so a lookup miss takes as long as a wrong password
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| {
+    hash_password("dummy password").expect("Implement Logging | Failed to hash dummy password")
+});
+
+/// Stores the role and one Argon2id hash per user. The file holds one `username role hash` entry per line, the hash is
+/// a PHC string (`$argon2id$v=19$...`) and therefore carries its own salt and parameters.
+#[derive(Debug, Default)]
+pub struct Credentials {
+    users: HashMap<String, User>
+}
+
+#[derive(Debug)]
+struct User {
+    role: Role,
+    hash: String
+}
+
+/// The authenticated caller of a request
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub username: String,
+    pub role: Role,
+    /// Devices the caller may report for, filled from the device registry
+    pub devices: BTreeSet<i32>
+}
+
+impl Identity {
+    /// Admins manage the whole fleet, everyone else only the devices registered to them
+    pub fn owns(&self, device_id: i32) -> bool {
+        self.role == Role::Admin || self.devices.contains(&device_id)
+    }
+}
+
+impl Credentials {
+    /// Loads the store from `path`. Empty lines and lines starting with `#` are ignored.
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let file: String = read_to_string(path).map_err(|err| Error::CredentialStoreError(format!("{path}: {err}")))?;
+
+        let mut users: HashMap<String, User> = HashMap::new();
+
+        for (line_number, line) in file.lines().enumerate() {
+            let line: &str = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+
+            match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some(username), Some(role), Some(hash), None) => {
+                    let role: Role = role.parse().map_err(|err| Error::CredentialStoreError(format!("{path}:{}: {err}", line_number + 1)))?;
+                    PasswordHash::new(hash).map_err(|_| Error::CredentialStoreError(format!("{path}:{}: invalid password hash", line_number + 1)))?;
+
+                    users.insert(username.to_string(), User { role, hash: hash.to_string() });
+                },
+                _ => return Err(Error::CredentialStoreError(format!("{path}:{}: expected `username role hash`", line_number + 1)))
+            }
+        }
+
+        users.shrink_to_fit();
+
+        Ok(Self { users })
+    }
+
+    /// Loads the store from `path`, or returns an empty store if the file doesn't exist yet
+    pub fn load_or_default(path: &str) -> Result<Self, Error> {
+        if std::path::Path::new(path).exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        let mut usernames: Vec<&String> = self.users.keys().collect();
+        usernames.sort();
+
+        let file: String = usernames.into_iter().map(|username| {
+            let User { role, hash } = &self.users[username];
+            format!("{username} {role} {hash}\n")
+        }).collect();
+
+        write_atomically(path, &file).map_err(|err| Error::CredentialStoreError(format!("{path}: {err}")))
+    }
+
+    /// Checks `password` against the stored hash. Unknown users are verified against a dummy hash, so the time
+    /// spent doesn't reveal whether a username exists. The hash comparison itself is constant-time.
+    pub fn verify(&self, username: &str, password: &str) -> Option<Identity> {
+        let user: Option<&User> = self.users.get(username);
+        let hash: &str = user.map_or(&DUMMY_HASH, |user| &user.hash);
+
+        let verified: bool = PasswordHash::new(hash)
+            .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+            .unwrap_or(false);
+
+        match user {
+            Some(user) if verified => Some(Identity { username: username.to_string(), role: user.role, devices: BTreeSet::new() }),
+            _ => None
+        }
+    }
+
+    /// Looks up a user without checking a password, for schemes that authenticate the user by other means
+    pub fn identity(&self, username: &str) -> Option<Identity> {
+        self.users.get(username).map(|user| Identity { username: username.to_string(), role: user.role, devices: BTreeSet::new() })
+    }
+
+    pub fn contains(&self, username: &str) -> bool {
+        self.users.contains_key(username)
+    }
+
+    /// Adds a new user, or replaces the role and password of an existing one
+    pub fn insert(&mut self, username: &str, role: Role, password: &str) -> Result<(), Error> {
+        if username.is_empty() || username.contains(char::is_whitespace) || username.contains(':') {
+            return Err(Error::CredentialStoreError(format!("Invalid username `{username}`")));
+        }
+
+        self.users.insert(username.to_string(), User { role, hash: hash_password(password)? });
+        Ok(())
+    }
+
+    /// Replaces the password of an existing user and keeps its role
+    pub fn set_password(&mut self, username: &str, password: &str) -> Result<(), Error> {
+        let hash: String = hash_password(password)?;
+
+        self.users.get_mut(username)
+            .map(|user| user.hash = hash)
+            .ok_or_else(|| Error::CredentialStoreError(format!("User `{username}` doesn't exist")))
+    }
+
+    pub fn set_role(&mut self, username: &str, role: Role) -> Result<(), Error> {
+        self.users.get_mut(username)
+            .map(|user| user.role = role)
+            .ok_or_else(|| Error::CredentialStoreError(format!("User `{username}` doesn't exist")))
+    }
+
+    /// Returns false if the user didn't exist
+    pub fn remove(&mut self, username: &str) -> bool {
+        self.users.remove(username).is_some()
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String, Error> {
+    let salt: SaltString = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| Error::PasswordHashingError(err.to_string()))
+}
@@ -0,0 +1,90 @@
+use crate::error::Error;
+use crate::util::write_atomically;
+
+use std::fs::read_to_string;
+use std::collections::{BTreeSet, HashMap};
+
+
+/// Default location of the device registry, relative to the working directory of the server
+pub const DEVICES_PATH: &str = ".devices";
+
+/// Maps usernames to the device IDs they are allowed to report for. The file holds one `username device_id...` entry
+/// per line, e.g. `user2 0 1 2`.
+#[derive(Debug, Default)]
+pub struct DeviceRegistry {
+    devices: HashMap<String, BTreeSet<i32>>
+}
+
+impl DeviceRegistry {
+    /// Loads the registry from `path`. Empty lines and lines starting with `#` are ignored.
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let file: String = read_to_string(path).map_err(|err| Error::DeviceRegistryError(format!("{path}: {err}")))?;
+
+        let mut devices: HashMap<String, BTreeSet<i32>> = HashMap::new();
+
+        for (line_number, line) in file.lines().enumerate() {
+            let line: &str = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let username: &str = fields.next().unwrap(); // A trimmed, non empty line has at least one field
+
+            let device_ids: BTreeSet<i32> = fields
+                .map(|device_id| device_id.parse::<i32>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| Error::DeviceRegistryError(format!("{path}:{}: device IDs must be integers", line_number + 1)))?;
+
+            devices.entry(username.to_string()).or_default().extend(device_ids);
+        }
+
+        devices.shrink_to_fit();
+
+        Ok(Self { devices })
+    }
+
+    /// Loads the registry from `path`, or returns an empty registry if the file doesn't exist yet
+    pub fn load_or_default(path: &str) -> Result<Self, Error> {
+        if std::path::Path::new(path).exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        let mut usernames: Vec<&String> = self.devices.keys().collect();
+        usernames.sort();
+
+        let file: String = usernames.into_iter()
+            .filter(|username| !self.devices[*username].is_empty())
+            .map(|username| {
+                let device_ids: Vec<String> = self.devices[username].iter().map(i32::to_string).collect();
+                format!("{username} {}\n", device_ids.join(" "))
+            }).collect();
+
+        write_atomically(path, &file).map_err(|err| Error::DeviceRegistryError(format!("{path}: {err}")))
+    }
+
+    /// Device IDs owned by `username`, empty if the user owns none
+    pub fn devices_of(&self, username: &str) -> BTreeSet<i32> {
+        self.devices.get(username).cloned().unwrap_or_default()
+    }
+
+    /// Returns false if the device was already assigned to the user
+    pub fn assign(&mut self, username: &str, device_id: i32) -> bool {
+        self.devices.entry(username.to_string()).or_default().insert(device_id)
+    }
+
+    /// Returns false if the device wasn't assigned to the user
+    pub fn unassign(&mut self, username: &str, device_id: i32) -> bool {
+        self.devices.get_mut(username).is_some_and(|device_ids| device_ids.remove(&device_id))
+    }
+
+    /// Removes every device assignment of the user
+    pub fn remove(&mut self, username: &str) {
+        self.devices.remove(username);
+    }
+}
@@ -0,0 +1,118 @@
+use serde::Serialize;
+
+use crate::util::unix_now;
+
+use std::cmp::Reverse;
+use std::net::IpAddr;
+use std::collections::HashMap;
+
+
+/// Failed attempts per username before it gets locked
+pub const USERNAME_FAILURE_THRESHOLD: u32 = 5;
+
+/// Failed attempts per source IP before it gets locked. Higher than per username, as several devices may share an IP.
+pub const IP_FAILURE_THRESHOLD: u32 = 20;
+
+/// Lockout after the first failure past the threshold, doubled with every further failure
+const BASE_LOCKOUT: u64 = 1;
+const MAX_LOCKOUT: u64 = 15 * 60;
+
+/// Failures are forgotten after this many seconds without a new one
+const FORGET_AFTER: u64 = 60 * 60;
+
+/// Above this many tracked subjects, forgotten ones are pruned on every failure
+const PRUNE_ABOVE: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Subject {
+    Username(String),
+    Ip(IpAddr)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Lockout {
+    pub subject: Subject,
+    pub failures: u32,
+    /// Unix timestamp in seconds, `None` if the subject is below the threshold
+    pub locked_until: Option<u64>,
+    #[serde(skip)]
+    last_failure: u64
+}
+
+/// Counts failed authentications per username and per source IP and locks them out with exponential back-off
+#[derive(Debug, Default)]
+pub struct Lockouts {
+    entries: HashMap<Subject, Lockout>
+}
+
+impl Lockouts {
+    /// Returns the number of seconds until the next attempt is allowed, if either subject is locked
+    pub fn retry_after(&self, username: Option<&str>, ip: Option<IpAddr>) -> Option<u64> {
+        let now: u64 = unix_now();
+
+        [username.map(|username| Subject::Username(username.to_string())), ip.map(Subject::Ip)]
+            .into_iter()
+            .flatten()
+            .filter_map(|subject| self.entries.get(&subject)?.locked_until)
+            .filter(|locked_until| *locked_until > now)
+            .map(|locked_until| locked_until - now)
+            .max()
+    }
+
+    pub fn record_failure(&mut self, username: Option<&str>, ip: Option<IpAddr>) {
+        let now: u64 = unix_now();
+
+        if self.entries.len() > PRUNE_ABOVE {
+            self.entries.retain(|_, lockout| now.saturating_sub(lockout.last_failure) < FORGET_AFTER || lockout.locked_until.is_some_and(|locked_until| locked_until > now));
+        }
+
+        let subjects = [
+            username.map(|username| (Subject::Username(username.to_string()), USERNAME_FAILURE_THRESHOLD)),
+            ip.map(|ip| (Subject::Ip(ip), IP_FAILURE_THRESHOLD))
+        ];
+
+        for (subject, threshold) in subjects.into_iter().flatten() {
+            let lockout: &mut Lockout = self.entries.entry(subject.clone()).or_insert(Lockout { subject, failures: 0, locked_until: None, last_failure: now });
+
+            if now.saturating_sub(lockout.last_failure) >= FORGET_AFTER {
+                lockout.failures = 0;
+            }
+
+            lockout.failures += 1;
+            lockout.last_failure = now;
+
+            if lockout.failures >= threshold {
+                let exponent: u32 = (lockout.failures - threshold).min(u64::BITS - 1);
+                lockout.locked_until = Some(now + BASE_LOCKOUT.saturating_mul(1 << exponent).min(MAX_LOCKOUT));
+            }
+        }
+    }
+
+    /// A successful login resets the username. The IP keeps its failures, otherwise one valid account would allow
+    /// guessing the passwords of all others from the same address.
+    pub fn record_success(&mut self, username: &str) {
+        self.entries.remove(&Subject::Username(username.to_string()));
+    }
+
+    pub fn list(&self) -> Vec<Lockout> {
+        let now: u64 = unix_now();
+
+        let mut lockouts: Vec<Lockout> = self.entries.values()
+            .filter(|lockout| now.saturating_sub(lockout.last_failure) < FORGET_AFTER || lockout.locked_until.is_some_and(|locked_until| locked_until > now))
+            .cloned()
+            .collect();
+
+        lockouts.sort_by_key(|lockout| Reverse(lockout.failures));
+        lockouts
+    }
+
+    /// Returns false if the subject wasn't tracked
+    pub fn clear(&mut self, subject: &Subject) -> bool {
+        self.entries.remove(subject).is_some()
+    }
+
+    pub fn clear_all(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,6 @@
+pub mod api_keys;
+pub mod credentials;
+pub mod devices;
+pub mod lockout;
+pub mod role;
+pub mod signature;
@@ -0,0 +1,53 @@
+use hyper::Method;
+use serde::{Serialize, Deserialize};
+
+use crate::error::Error;
+
+use std::fmt;
+use std::str::FromStr;
+
+
+/// Every user has exactly one role, which decides the HTTP methods it may use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Sensors reporting their readings. May only POST.
+    Device,
+    /// Dashboards and scripts reading data. May only GET.
+    Analyst,
+    /// May do everything, including PUT and DELETE
+    Admin
+}
+
+impl Role {
+    pub fn permits(&self, method: &Method) -> bool {
+        match self {
+            Self::Device => method == Method::POST,
+            Self::Analyst => method == Method::GET,
+            Self::Admin => true
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = Error;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "device" => Ok(Self::Device),
+            "analyst" => Ok(Self::Analyst),
+            "admin" => Ok(Self::Admin),
+            _ => Err(Error::CredentialStoreError(format!("Unknown role `{role}`, expected device, analyst or admin")))
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Device => write!(f, "device"),
+            Self::Analyst => write!(f, "analyst"),
+            Self::Admin => write!(f, "admin")
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! HMAC-SHA256 request signing for devices that can't do TLS.
+//!
+//! A signed request carries `Authorization: HMAC-SHA256 <username>:<timestamp>:<nonce>:<signature>`, where `timestamp`
+//! is a unix timestamp in seconds, `nonce` a random string of at most 64 characters and `signature` the base64 encoded
+//! HMAC-SHA256 over
+//!
+//! ```text
+//! <METHOD>\n<path and query>\n<timestamp>\n<nonce>\n<body>
+//! ```
+//!
+//! keyed with the device's shared secret. Requests outside of [`MAX_CLOCK_SKEW`] or reusing a nonce are rejected.
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::prelude::*;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::error::Error;
+use crate::util::{unix_now, write_atomically};
+
+use std::fs::read_to_string;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+
+/// Default location of the shared device secrets, relative to the working directory of the server
+pub const DEVICE_SECRETS_PATH: &str = ".device_secrets";
+
+/// Maximum difference in seconds between the signed timestamp and the server clock
+pub const MAX_CLOCK_SKEW: u64 = 300;
+
+/// Maximum number of nonces remembered at once
+pub const NONCE_CACHE_CAPACITY: usize = 100_000;
+
+const MAX_NONCE_LENGTH: usize = 64;
+
+/// Shared secrets used to verify signed requests. HMAC needs the secret itself, so unlike passwords these can't be
+/// hashed. The file holds one `username secret` pair per line, the secret is base64 encoded.
+#[derive(Debug, Default)]
+pub struct DeviceSecrets {
+    secrets: HashMap<String, Vec<u8>>
+}
+
+impl DeviceSecrets {
+    /// Loads the secrets from `path`. Empty lines and lines starting with `#` are ignored.
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let file: String = read_to_string(path).map_err(|err| Error::DeviceSecretStoreError(format!("{path}: {err}")))?;
+
+        let mut secrets: HashMap<String, Vec<u8>> = HashMap::new();
+
+        for (line_number, line) in file.lines().enumerate() {
+            let line: &str = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+
+            match (fields.next(), fields.next().map(|secret| BASE64_STANDARD.decode(secret)), fields.next()) {
+                (Some(username), Some(Ok(secret)), None) => {
+                    secrets.insert(username.to_string(), secret);
+                },
+                _ => return Err(Error::DeviceSecretStoreError(format!("{path}:{}: expected `username secret`", line_number + 1)))
+            }
+        }
+
+        Ok(Self { secrets })
+    }
+
+    /// Loads the secrets from `path`, or returns an empty store if the file doesn't exist yet
+    pub fn load_or_default(path: &str) -> Result<Self, Error> {
+        if std::path::Path::new(path).exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        let mut usernames: Vec<&String> = self.secrets.keys().collect();
+        usernames.sort();
+
+        let file: String = usernames.into_iter()
+            .map(|username| format!("{username} {}\n", BASE64_STANDARD.encode(&self.secrets[username])))
+            .collect();
+
+        write_atomically(path, &file).map_err(|err| Error::DeviceSecretStoreError(format!("{path}: {err}")))
+    }
+
+    /// Generates a new 256 bit secret for the user, replacing the previous one, and returns it base64 encoded
+    pub fn generate(&mut self, username: &str) -> String {
+        let mut secret: Vec<u8> = vec![0; 32];
+        OsRng.fill_bytes(&mut secret);
+
+        let encoded: String = BASE64_STANDARD.encode(&secret);
+        self.secrets.insert(username.to_string(), secret);
+
+        encoded
+    }
+
+    /// Returns false if the user had no secret
+    pub fn remove(&mut self, username: &str) -> bool {
+        self.secrets.remove(username).is_some()
+    }
+
+    /// Checks everything about a signed request that doesn't need the body: the format, the timestamp and that the user
+    /// has a secret. `credentials` is the part of the `Authorization` header after `HMAC-SHA256 `.
+    pub fn signed_request<'a>(&'a self, credentials: &'a str) -> Result<SignedRequest<'a>, Error> {
+        let mut fields = credentials.trim().splitn(4, ':');
+
+        let (Some(username), Some(timestamp), Some(nonce), Some(signature)) = (fields.next(), fields.next(), fields.next(), fields.next()) else {
+            return Err(Error::InvalidAuthentificationFormat);
+        };
+
+        let timestamp: u64 = timestamp.parse().map_err(|_| Error::InvalidAuthentificationFormat)?;
+        let signature: Vec<u8> = BASE64_STANDARD.decode(signature).map_err(|_| Error::DecodeError)?;
+
+        if nonce.is_empty() || nonce.len() > MAX_NONCE_LENGTH {
+            return Err(Error::InvalidAuthentificationFormat);
+        }
+
+        if unix_now().abs_diff(timestamp) > MAX_CLOCK_SKEW {
+            return Err(Error::StaleSignature);
+        }
+
+        // Unknown users fail the same way as a wrong signature
+        let secret: &[u8] = self.secrets.get(username).ok_or(Error::InvalidSignature)?;
+
+        Ok(SignedRequest { username, timestamp, nonce, signature, secret })
+    }
+}
+
+/// A signed request whose header passed [`DeviceSecrets::signed_request`], waiting for its body
+#[derive(Debug)]
+pub struct SignedRequest<'a> {
+    username: &'a str,
+    timestamp: u64,
+    nonce: &'a str,
+    signature: Vec<u8>,
+    secret: &'a [u8]
+}
+
+impl SignedRequest<'_> {
+    /// Verifies the signature and returns the username the request was signed by
+    pub fn verify(self, method: &str, path_and_query: &str, body: &[u8], nonces: &mut NonceCache) -> Result<String, Error> {
+        let mut mac: Hmac<Sha256> = Hmac::<Sha256>::new_from_slice(self.secret).expect("HMAC accepts keys of any length");
+        mac.update(format!("{method}\n{path_and_query}\n{}\n{}\n", self.timestamp, self.nonce).as_bytes());
+        mac.update(body);
+
+        // Constant-time comparison
+        mac.verify_slice(&self.signature).map_err(|_| Error::InvalidSignature)?;
+
+        // Only inserted after the signature, so unauthenticated requests can't fill the nonce cache
+        nonces.insert(self.username, self.nonce)?;
+
+        Ok(self.username.to_string())
+    }
+}
+
+/// Remembers recently used nonces per user. A signature stays valid for [`MAX_CLOCK_SKEW`] seconds on either side of
+/// its timestamp, so a nonce has to be kept for twice that long after it was first seen. Older nonces are evicted.
+#[derive(Debug)]
+pub struct NonceCache {
+    seen: HashSet<(String, String)>,
+    /// Insertion order together with the time of insertion, used for eviction
+    order: VecDeque<(u64, (String, String))>,
+    capacity: usize
+}
+
+impl NonceCache {
+    pub fn new(capacity: usize) -> Self {
+        Self { seen: HashSet::new(), order: VecDeque::new(), capacity }
+    }
+
+    /// Fails if the nonce was already used by this user. If the cache is full, new nonces are rejected instead of
+    /// evicting ones that are still valid, as that would reopen them for replay.
+    pub fn insert(&mut self, username: &str, nonce: &str) -> Result<(), Error> {
+        let now: u64 = unix_now();
+
+        while let Some((inserted, _)) = self.order.front() {
+            if now.saturating_sub(*inserted) <= 2 * MAX_CLOCK_SKEW {
+                break;
+            }
+
+            let (_, key) = self.order.pop_front().unwrap(); // Safe, as front() just returned an element
+            self.seen.remove(&key);
+        }
+
+        let key: (String, String) = (username.to_string(), nonce.to_string());
+
+        if self.seen.contains(&key) {
+            Err(Error::ReplayedSignature)
+        } else if self.seen.len() >= self.capacity {
+            Err(Error::NonceCacheFull)
+        } else {
+            self.seen.insert(key.clone());
+            self.order.push_back((now, key));
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use crate::error::Error;
+use crate::config::{AuthConfig, Config, Flag};
+use crate::authentification::role::Role;
+use crate::authentification::devices::DeviceRegistry;
+use crate::authentification::signature::DeviceSecrets;
+use crate::authentification::credentials::Credentials;
+use crate::data_managment::migrations::{self, Migration};
+use crate::util::{init_db, start_embedded_db};
+
+use postgres::Client;
+
+use std::io::{self, BufRead, IsTerminal};
+
+
+const USAGE: &str = "Usage:
+    server [--<setting> <value>...]     Start the IoT-Service
+    server user add <username> <role>   Add a user, the password is read from stdin
+    server user rotate <username>       Replace the password of an existing user
+    server user role <username> <role>  Change the role of an existing user
+    server user secret <username>       Generate a shared secret for HMAC-SHA256 signed requests
+    server user remove <username>       Remove a user, its devices and its secret
+    server device add <username> <id>   Allow a user to report for a device ID
+    server device remove <username> <id>
+                                        Revoke a device ID from a user
+    server migrate [--dry-run]          Apply pending schema migrations, or only list them with --dry-run.
+                                        The server also migrates on startup.
+
+Roles:
+    device      May only POST
+    analyst     May only GET
+    admin       May POST, GET, PUT and DELETE for every device
+
+Settings:
+    --config <path>                     TOML file to read, defaults to server.toml if present
+    --server.address, --server.port, --server.shutdown_timeout, --server.max_body_size, --server.tls.cert,
+    --server.tls.key, --server.tls.client_ca,
+    --database.url, --database.embedded, --database.pg_ctl, --database.data_directory, --database.schemas,
+    --auth.credentials, --auth.devices, --auth.api_keys, --auth.device_secrets,
+    --worker.pool_size, --worker.queue_capacity, --worker.max_exports, --worker.batch_size,
+    --worker.batch_delay_ms, --worker.connect_attempts, --worker.connect_retry_interval,
+    --worker.connect_max_retry_interval,
+    --clock.skew_threshold_ms, --clock.skew_action, --query.max_page_size
+                                        Every setting can also be given as environment variable,
+                                        e.g. IOT_SERVER_PORT for --server.port";
+
+/// Administrative commands, which run instead of the server
+pub enum Command {
+    AddUser(String, Role),
+    RotateUser(String),
+    ChangeRole(String, Role),
+    GenerateSecret(String),
+    RemoveUser(String),
+    AssignDevice(String, i32),
+    UnassignDevice(String, i32),
+    /// `true` for a dry run
+    Migrate(bool),
+    Help
+}
+
+/// Flags that don't take a value. They are passed on as positional arguments.
+const SWITCHES: [&str; 1] = ["dry-run"];
+
+impl Command {
+    /// Returns `None` if no subcommand was given, meaning the server should be started. `args` must not contain flags,
+    /// see [`split_flags`].
+    pub fn parse(args: &[String]) -> Option<Self> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        let command: Self = match args.as_slice() {
+            [] => return None,
+            ["user", "add", username, role] => role.parse().map_or(Self::Help, |role| Self::AddUser(username.to_string(), role)),
+            ["user", "rotate", username] => Self::RotateUser(username.to_string()),
+            ["user", "role", username, role] => role.parse().map_or(Self::Help, |role| Self::ChangeRole(username.to_string(), role)),
+            ["user", "secret", username] => Self::GenerateSecret(username.to_string()),
+            ["user", "remove", username] => Self::RemoveUser(username.to_string()),
+            ["device", "add", username, device_id] => device_id.parse().map_or(Self::Help, |device_id| Self::AssignDevice(username.to_string(), device_id)),
+            ["device", "remove", username, device_id] => device_id.parse().map_or(Self::Help, |device_id| Self::UnassignDevice(username.to_string(), device_id)),
+            ["migrate"] => Self::Migrate(false),
+            ["migrate", "--dry-run"] => Self::Migrate(true),
+            _ => Self::Help
+        };
+
+        Some(command)
+    }
+
+    pub fn run(self, config: &Config) -> Result<(), Error> {
+        let AuthConfig { credentials: credentials_path, devices: devices_path, device_secrets: device_secrets_path, .. } = &config.auth;
+
+        match self {
+            Self::AddUser(username, role) => {
+                let mut credentials: Credentials = Credentials::load_or_default(credentials_path)?;
+
+                if credentials.contains(&username) {
+                    return Err(Error::CredentialStoreError(format!("User `{username}` already exists, use `user rotate`")));
+                }
+
+                credentials.insert(&username, role, &read_password()?)?;
+                credentials.save(credentials_path)?;
+                println!("Added {role} `{username}`");
+            },
+            Self::RotateUser(username) => {
+                let mut credentials: Credentials = Credentials::load(credentials_path)?;
+
+                if !credentials.contains(&username) {
+                    return Err(Error::CredentialStoreError(format!("User `{username}` doesn't exist, use `user add`")));
+                }
+
+                credentials.set_password(&username, &read_password()?)?;
+                credentials.save(credentials_path)?;
+                println!("Rotated password of `{username}`");
+            },
+            Self::ChangeRole(username, role) => {
+                let mut credentials: Credentials = Credentials::load(credentials_path)?;
+
+                credentials.set_role(&username, role)?;
+                credentials.save(credentials_path)?;
+                println!("`{username}` is now {role}");
+            },
+            Self::RemoveUser(username) => {
+                let mut credentials: Credentials = Credentials::load(credentials_path)?;
+
+                if !credentials.remove(&username) {
+                    return Err(Error::CredentialStoreError(format!("User `{username}` doesn't exist")));
+                }
+
+                credentials.save(credentials_path)?;
+
+                let mut devices: DeviceRegistry = DeviceRegistry::load_or_default(devices_path)?;
+                devices.remove(&username);
+                devices.save(devices_path)?;
+
+                let mut secrets: DeviceSecrets = DeviceSecrets::load_or_default(device_secrets_path)?;
+                if secrets.remove(&username) {
+                    secrets.save(device_secrets_path)?;
+                }
+
+                println!("Removed user `{username}`");
+            },
+            Self::GenerateSecret(username) => {
+                if !Credentials::load(credentials_path)?.contains(&username) {
+                    return Err(Error::CredentialStoreError(format!("User `{username}` doesn't exist")));
+                }
+
+                let mut secrets: DeviceSecrets = DeviceSecrets::load_or_default(device_secrets_path)?;
+                let secret: String = secrets.generate(&username);
+                secrets.save(device_secrets_path)?;
+
+                println!("Shared secret of `{username}` (base64): {secret}");
+            },
+            Self::AssignDevice(username, device_id) => {
+                if !Credentials::load(credentials_path)?.contains(&username) {
+                    return Err(Error::CredentialStoreError(format!("User `{username}` doesn't exist")));
+                }
+
+                let mut devices: DeviceRegistry = DeviceRegistry::load_or_default(devices_path)?;
+
+                if !devices.assign(&username, device_id) {
+                    return Err(Error::DeviceRegistryError(format!("Device `{device_id}` is already registered to `{username}`")));
+                }
+
+                devices.save(devices_path)?;
+                println!("Registered device `{device_id}` to `{username}`");
+            },
+            Self::UnassignDevice(username, device_id) => {
+                let mut devices: DeviceRegistry = DeviceRegistry::load_or_default(devices_path)?;
+
+                if !devices.unassign(&username, device_id) {
+                    return Err(Error::DeviceRegistryError(format!("Device `{device_id}` is not registered to `{username}`")));
+                }
+
+                devices.save(devices_path)?;
+                println!("Revoked device `{device_id}` from `{username}`");
+            },
+            Self::Migrate(dry_run) => {
+                if config.database.embedded {
+                    start_embedded_db(&config.database)?;
+                }
+
+                let mut client: Client = init_db(&config.database, &config.worker)?;
+
+                if dry_run {
+                    let pending: Vec<&Migration> = migrations::pending(&mut client)?;
+                    println!("Database schema is at version {}, {} pending migrations", migrations::current_version(&mut client)?, pending.len());
+
+                    for migration in pending {
+                        println!("\n-- {:04} {}\n{}", migration.version, migration.name, migration.sql.trim_end());
+                    }
+                } else {
+                    let applied: Vec<&Migration> = migrations::migrate(&mut client)?;
+                    println!("Applied {} migrations, database schema is at version {}", applied.len(), migrations::current_version(&mut client)?);
+                }
+            },
+            Self::Help => println!("{USAGE}")
+        }
+
+        Ok(())
+    }
+}
+
+/// Separates `--key value` flags from the positional arguments. Flags may appear anywhere, e.g.
+/// `server --config prod.toml user add admin admin`.
+pub fn split_flags<I: Iterator<Item = String>>(args: I) -> Result<(Vec<String>, Vec<Flag>), Error> {
+    let mut positionals: Vec<String> = Vec::new();
+    let mut flags: Vec<Flag> = Vec::new();
+    let mut args = args.peekable();
+
+    while let Some(arg) = args.next() {
+        let Some(key) = arg.strip_prefix("--").filter(|key| !SWITCHES.contains(key)) else {
+            positionals.push(arg);
+            continue;
+        };
+
+        // Both `--key value` and `--key=value` are accepted
+        match key.split_once('=') {
+            Some((key, value)) => flags.push((key.to_string(), value.to_string())),
+            None => match args.next_if(|value| !value.starts_with("--")) {
+                Some(value) => flags.push((key.to_string(), value)),
+                None => return Err(Error::ConfigError(format!("--{key} requires a value")))
+            }
+        }
+    }
+
+    Ok((positionals, flags))
+}
+
+/// Reads a single line from stdin, so passwords can be piped in by provisioning scripts. Typed passwords are not echoed.
+fn read_password() -> Result<String, Error> {
+    let password: String = if io::stdin().is_terminal() {
+        rpassword::prompt_password("Password: ").map_err(|err| Error::CredentialStoreError(err.to_string()))?
+    } else {
+        let mut password: String = String::new();
+        io::stdin().lock().read_line(&mut password).map_err(|err| Error::CredentialStoreError(err.to_string()))?;
+        password
+    };
+
+    let password: &str = password.trim_end_matches(['\r', '\n']);
+
+    if password.is_empty() {
+        Err(Error::CredentialStoreError("Password must not be empty".to_string()))
+    } else {
+        Ok(password.to_string())
+    }
+}
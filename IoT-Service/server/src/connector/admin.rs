@@ -0,0 +1,192 @@
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::{Serialize, Deserialize};
+use crossbeam::channel::Sender;
+
+use crate::error::Error;
+use crate::util::unix_now;
+use crate::authentification::role::Role;
+use crate::authentification::credentials::Identity;
+use crate::authentification::lockout::{Lockout, Lockouts, Subject};
+use crate::authentification::api_keys::{ApiKey, ApiKeys};
+use crate::data_managment::message_passing::Message;
+use crate::data_managment::worker::{QueueMetrics, METRICS};
+use crate::data_managment::schema::{schemas, Schema};
+use super::response::build_response;
+use super::body::payload_too_large;
+use super::middleware::{parse_body, stores};
+
+use std::net::IpAddr;
+use std::sync::{MutexGuard, RwLockWriteGuard};
+use std::collections::{BTreeSet, HashMap};
+
+
+/// Issue (POST), list (GET) and revoke (DELETE `?id=`) API keys. Admins only.
+pub const KEYS_ENDPOINT: &str = "/api/v1/keys";
+
+/// List (GET) and clear (DELETE `?username=`, `?ip=` or everything without a query) authentication lockouts. Admins only.
+pub const LOCKOUTS_ENDPOINT: &str = "/api/v1/lockouts";
+
+/// Queue depth and worker utilisation (GET). Admins only.
+pub const METRICS_ENDPOINT: &str = "/api/v1/metrics";
+
+/// List (GET, `?name=` for a single one), add or replace (POST) and remove (DELETE `?name=`) dataframe schemas.
+/// Everyone may read them, changes are restricted to admins.
+pub const SCHEMAS_ENDPOINT: &str = "/api/v1/schemas";
+
+#[derive(Debug, Deserialize)]
+struct KeyRequest {
+    name: String,
+    role: Role,
+    #[serde(default)]
+    devices: BTreeSet<i32>,
+    /// Lifetime of the key in seconds, the key never expires if omitted
+    expires_in: Option<u64>
+}
+
+#[derive(Debug, Serialize)]
+struct IssuedKey {
+    #[serde(flatten)]
+    metadata: ApiKey,
+    /// Only returned once, the server keeps nothing but a hash
+    key: String
+}
+
+pub async fn keys_request(mut req: Request<Body>, identity: &Identity) -> Response<Body> {
+    if identity.role != Role::Admin {
+        return build_response(StatusCode::FORBIDDEN, "Only admins may manage API keys".to_string()).expect("Implement Logging");
+    }
+
+    match *req.method() {
+        Method::POST => match parse_body::<KeyRequest>(req.body_mut()).await {
+            Ok(KeyRequest { name, role, devices, expires_in }) => {
+                let expires_at: Option<u64> = expires_in.map(|expires_in| unix_now().saturating_add(expires_in));
+
+                let mut api_keys: RwLockWriteGuard<ApiKeys> = stores().api_keys.write().expect("Implement Logging | API key store poisoned");
+                let (metadata, key) = api_keys.issue(&name, role, devices, expires_at);
+
+                match api_keys.save(&stores().api_keys_path) {
+                    Ok(()) => build_response(StatusCode::CREATED, serde_json::to_string(&IssuedKey { metadata, key }).unwrap()).expect("Implement Logging"),
+                    Err(err) => {
+                        api_keys.revoke(&metadata.id);
+                        build_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).expect("Implement Logging")
+                    }
+                }
+            },
+            Err(Error::TransmissionError) => build_response(StatusCode::BAD_REQUEST, "Data transmission failed".to_string()).expect("Implement logging"),
+            Err(Error::PayloadTooLarge) => payload_too_large(),
+            Err(_) => build_response(StatusCode::BAD_REQUEST, "Invalid data format. Expected name, role and optionally devices and expires_in".to_string()).expect("Implement logging")
+        },
+        Method::GET => {
+            let keys: Vec<ApiKey> = stores().api_keys.read().expect("Implement Logging | API key store poisoned").list();
+            build_response(StatusCode::OK, serde_json::to_string(&keys).unwrap()).expect("Implement Logging")
+        },
+        Method::DELETE => {
+            let query: HashMap<String, String> = req.uri().query().and_then(|query| serde_urlencoded::from_str(query).ok()).unwrap_or_default();
+
+            let Some(id) = query.get("id") else {
+                return build_response(StatusCode::BAD_REQUEST, "No key id found. Consider adding ?id=".to_string()).expect("Implement logging");
+            };
+
+            let mut api_keys: RwLockWriteGuard<ApiKeys> = stores().api_keys.write().expect("Implement Logging | API key store poisoned");
+
+            if !api_keys.revoke(id) {
+                return build_response(StatusCode::NOT_FOUND, format!("No API key with id `{id}`")).expect("Implement Logging");
+            }
+
+            match api_keys.save(&stores().api_keys_path) {
+                Ok(()) => build_response(StatusCode::OK, "".to_string()).expect("Implement Logging"),
+                Err(err) => build_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).expect("Implement Logging")
+            }
+        },
+        _ => build_response(StatusCode::NOT_IMPLEMENTED, "".to_string()).expect("Implement logging")
+    }
+}
+
+pub fn lockouts_request(req: Request<Body>, identity: &Identity) -> Response<Body> {
+    if identity.role != Role::Admin {
+        return build_response(StatusCode::FORBIDDEN, "Only admins may manage lockouts".to_string()).expect("Implement Logging");
+    }
+
+    let mut lockouts: MutexGuard<Lockouts> = stores().lockouts.lock().expect("Implement Logging | lockouts poisoned");
+
+    match *req.method() {
+        Method::GET => {
+            let list: Vec<Lockout> = lockouts.list();
+            build_response(StatusCode::OK, serde_json::to_string(&list).unwrap()).expect("Implement Logging")
+        },
+        Method::DELETE => {
+            let query: HashMap<String, String> = req.uri().query().and_then(|query| serde_urlencoded::from_str(query).ok()).unwrap_or_default();
+
+            let subject: Subject = match (query.get("username"), query.get("ip").map(|ip| ip.parse::<IpAddr>())) {
+                (None, None) => {
+                    lockouts.clear_all();
+                    return build_response(StatusCode::OK, "".to_string()).expect("Implement Logging");
+                },
+                (Some(username), None) => Subject::Username(username.to_string()),
+                (None, Some(Ok(ip))) => Subject::Ip(ip),
+                _ => return build_response(StatusCode::BAD_REQUEST, "Expected either ?username= or a valid ?ip=".to_string()).expect("Implement logging")
+            };
+
+            if lockouts.clear(&subject) {
+                build_response(StatusCode::OK, "".to_string()).expect("Implement Logging")
+            } else {
+                build_response(StatusCode::NOT_FOUND, "No lockout for this username or IP".to_string()).expect("Implement Logging")
+            }
+        },
+        _ => build_response(StatusCode::NOT_IMPLEMENTED, "".to_string()).expect("Implement logging")
+    }
+}
+
+pub fn metrics_request(req: Request<Body>, identity: &Identity, sender: &Sender<Message>) -> Response<Body> {
+    if identity.role != Role::Admin {
+        return build_response(StatusCode::FORBIDDEN, "Only admins may read metrics".to_string()).expect("Implement Logging");
+    }
+
+    match *req.method() {
+        Method::GET => {
+            let metrics: QueueMetrics = METRICS.snapshot(sender);
+            build_response(StatusCode::OK, serde_json::to_string(&metrics).unwrap()).expect("Implement Logging")
+        },
+        _ => build_response(StatusCode::NOT_IMPLEMENTED, "".to_string()).expect("Implement logging")
+    }
+}
+
+pub async fn schemas_request(mut req: Request<Body>, identity: &Identity) -> Response<Body> {
+    if req.method() != Method::GET && identity.role != Role::Admin {
+        return build_response(StatusCode::FORBIDDEN, "Only admins may manage schemas".to_string()).expect("Implement Logging");
+    }
+
+    let query: HashMap<String, String> = req.uri().query().and_then(|query| serde_urlencoded::from_str(query).ok()).unwrap_or_default();
+
+    match *req.method() {
+        Method::GET => match query.get("name") {
+            Some(name) => match schemas().read().get(name) {
+                Some(schema) => build_response(StatusCode::OK, serde_json::to_string(schema).unwrap()).expect("Implement Logging"),
+                None => build_response(StatusCode::NOT_FOUND, format!("No schema named `{name}`")).expect("Implement Logging")
+            },
+            None => build_response(StatusCode::OK, serde_json::to_string(&schemas().read().list()).unwrap()).expect("Implement Logging")
+        },
+        Method::POST => match parse_body::<Schema>(req.body_mut()).await {
+            Ok(schema) => match schemas().put(schema) {
+                Ok(true) => build_response(StatusCode::OK, "".to_string()).expect("Implement Logging"),
+                Ok(false) => build_response(StatusCode::CREATED, "".to_string()).expect("Implement Logging"),
+                Err(err) => build_response(StatusCode::BAD_REQUEST, err.to_string()).expect("Implement Logging")
+            },
+            Err(Error::TransmissionError) => build_response(StatusCode::BAD_REQUEST, "Data transmission failed".to_string()).expect("Implement logging"),
+            Err(Error::PayloadTooLarge) => payload_too_large(),
+            Err(_) => build_response(StatusCode::BAD_REQUEST, "Invalid data format. Expected name, fields and optionally devices".to_string()).expect("Implement logging")
+        },
+        Method::DELETE => {
+            let Some(name) = query.get("name") else {
+                return build_response(StatusCode::BAD_REQUEST, "No schema name found. Consider adding ?name=".to_string()).expect("Implement logging");
+            };
+
+            match schemas().remove(name) {
+                Ok(true) => build_response(StatusCode::OK, "".to_string()).expect("Implement Logging"),
+                Ok(false) => build_response(StatusCode::NOT_FOUND, format!("No schema named `{name}`")).expect("Implement Logging"),
+                Err(err) => build_response(StatusCode::BAD_REQUEST, err.to_string()).expect("Implement Logging")
+            }
+        },
+        _ => build_response(StatusCode::NOT_IMPLEMENTED, "".to_string()).expect("Implement logging")
+    }
+}
@@ -0,0 +1,52 @@
+//! Statistics per time bucket, see [`crate::data_managment::aggregation`]
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use tokio::sync::oneshot;
+use crossbeam::channel::Sender;
+
+use crate::error::Error;
+use crate::data_managment::aggregation::{Aggregation, AggregationQuery};
+use crate::data_managment::schema::schemas;
+use crate::data_managment::dataformat::Query;
+use crate::data_managment::message_passing::{self, Message};
+use crate::data_managment::worker::dispatch;
+use super::response::build_response;
+use super::middleware::dispatch_failed;
+
+use std::sync::Arc;
+
+
+/// Min, max, mean, count and percentiles per bucket (GET), e.g.
+/// `?device_id=1&time_start=...&time_end=...&bucket=1h&fields=temp&percentiles=50,95`
+pub const AGGREGATE_ENDPOINT: &str = "/api/v1/aggregate";
+
+pub async fn aggregate_request(req: &Request<Body>, sender: Arc<Sender<Message>>) -> Response<Body> {
+    if req.method() != Method::GET {
+        return build_response(StatusCode::NOT_IMPLEMENTED, "".to_string()).expect("Implement logging");
+    }
+
+    let query: &str = req.uri().query().unwrap_or_default();
+
+    // Both are read from the same parameters, each ignoring those of the other
+    let aggregation: Result<Aggregation, Error> = serde_urlencoded::from_str::<AggregationQuery>(query)
+        .and_then(|aggregation| Ok((aggregation, serde_urlencoded::from_str::<Query>(query)?)))
+        .map_err(|_| Error::QueryParsingError)
+        .and_then(|(aggregation, query)| Aggregation::from_query(&aggregation, &query, &schemas().read()));
+
+    let aggregation: Aggregation = match aggregation {
+        Ok(aggregation) => aggregation,
+        Err(Error::InvalidFilter(reason)) => return build_response(StatusCode::BAD_REQUEST, reason).expect("Implement logging"),
+        Err(_) => return build_response(StatusCode::BAD_REQUEST, "Couldn't parse query, bucket is required".to_string()).expect("Implement logging")
+    };
+
+    let (response_sender, response_receiver) = oneshot::channel::<Box<dyn message_passing::Response>>();
+
+    if let Err(err) = dispatch(&sender, Message::Aggregate(aggregation, response_sender)) {
+        return dispatch_failed(err);
+    }
+
+    match response_receiver.await {
+        Ok(response) => build_response(response.status_code(), response.content()).expect("Implement Logging"),
+        Err(_) => build_response(StatusCode::BAD_GATEWAY, "Worker timed out".to_string()).expect("Implement Logging")
+    }
+}
@@ -0,0 +1,53 @@
+//! Request bodies are read into memory before they are parsed or their signature is checked, so their size is capped
+//! by `server.max_body_size`.
+
+use hyper::{Body, Response, StatusCode};
+use hyper::body::{Bytes, HttpBody};
+
+use crate::error::Error;
+use crate::config::ServerConfig;
+use super::response::build_response;
+
+use std::sync::OnceLock;
+
+
+static MAX_BODY_SIZE: OnceLock<usize> = OnceLock::new();
+
+/// Has to be called before the first request is served
+pub fn init_body_limit(config: &ServerConfig) -> Result<(), Error> {
+    MAX_BODY_SIZE.set(config.max_body_size).map_err(|_| Error::ConfigError("Body size limit is already initialised".to_string()))
+}
+
+pub fn max_body_size() -> usize {
+    *MAX_BODY_SIZE.get().expect("init_body_limit has to be called before serving requests")
+}
+
+/// Reads the whole body, or fails with [`Error::PayloadTooLarge`] as soon as it exceeds [`max_body_size`]. A body
+/// announcing a larger `Content-Length` is rejected without reading any of it.
+pub async fn read_body(body: &mut Body) -> Result<Bytes, Error> {
+    let limit: usize = max_body_size();
+
+    // The lower bound is the Content-Length, if one was sent
+    if body.size_hint().lower() > limit as u64 {
+        return Err(Error::PayloadTooLarge);
+    }
+
+    let mut collected: Vec<u8> = Vec::new();
+
+    while let Some(chunk) = body.data().await {
+        let chunk: Bytes = chunk.map_err(|_| Error::TransmissionError)?;
+
+        if collected.len() + chunk.len() > limit {
+            return Err(Error::PayloadTooLarge);
+        }
+
+        collected.extend_from_slice(&chunk);
+    }
+
+    Ok(Bytes::from(collected))
+}
+
+/// Response for a body rejected with [`Error::PayloadTooLarge`]
+pub fn payload_too_large() -> Response<Body> {
+    build_response(StatusCode::PAYLOAD_TOO_LARGE, format!("At most {} bytes per request", max_body_size())).expect("Implement Logging")
+}
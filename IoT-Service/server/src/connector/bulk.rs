@@ -0,0 +1,152 @@
+//! Bulk upload for devices that buffer readings, e.g. while offline. The body is either a JSON array of dataframes or
+//! newline delimited JSON (one dataframe per line). Every dataframe is validated on its own, the valid ones are
+//! inserted in a single transaction and the response lists the outcome per item.
+
+use hyper::{Body, Response, StatusCode, body::Bytes};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::oneshot;
+use crossbeam::channel::Sender;
+
+use crate::error::Error;
+use crate::authentification::credentials::Identity;
+use crate::data_managment::clock::clocks;
+use crate::data_managment::schema::{schemas, Schemas};
+use crate::data_managment::dataformat::{DataFrame, MAX_MESSAGE_ID_LENGTH};
+use crate::data_managment::message_passing::{self, Message};
+use crate::data_managment::worker::dispatch;
+use super::body::{payload_too_large, read_body};
+use super::response::build_response;
+use super::middleware::dispatch_failed;
+
+use std::sync::{Arc, RwLockReadGuard};
+
+
+/// Upload (POST) a JSON array or NDJSON stream of dataframes
+pub const BULK_ENDPOINT: &str = "/api/v1/bulk";
+
+/// Maximum number of dataframes per request
+pub const MAX_BULK_SIZE: usize = 10_000;
+
+#[derive(Debug, Serialize)]
+struct Summary {
+    accepted: usize,
+    /// Accepted, but not stored again as the message ID was already known
+    duplicates: usize,
+    rejected: usize,
+    results: Vec<ItemResult>
+}
+
+#[derive(Debug, Serialize)]
+struct ItemResult {
+    /// Position in the array, or the number of the non-empty line for NDJSON, starting at 0
+    index: usize,
+    #[serde(flatten)]
+    status: ItemStatus
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum ItemStatus {
+    Accepted,
+    Duplicate,
+    Rejected { reason: String }
+}
+
+pub async fn bulk_request(data: &mut Body, sender: Arc<Sender<Message>>, identity: &Identity) -> Response<Body> {
+    let body: Bytes = match read_body(data).await {
+        Ok(body) => body,
+        Err(Error::PayloadTooLarge) => return payload_too_large(),
+        Err(_) => return build_response(StatusCode::BAD_REQUEST, "Data transmission failed".to_string()).expect("Implement logging")
+    };
+
+    let items: Vec<Result<DataFrame, String>> = match parse_items(&body) {
+        Ok(items) if items.is_empty() => return build_response(StatusCode::BAD_REQUEST, "No dataframes found".to_string()).expect("Implement logging"),
+        Ok(items) if items.len() > MAX_BULK_SIZE => return build_response(StatusCode::PAYLOAD_TOO_LARGE, format!("At most {MAX_BULK_SIZE} dataframes per request")).expect("Implement logging"),
+        Ok(items) => items,
+        Err(_) => return build_response(StatusCode::BAD_REQUEST, "Invalid data format. Expected a JSON array or one JSON object per line".to_string()).expect("Implement logging")
+    };
+
+    let mut dataframes: Vec<DataFrame> = Vec::with_capacity(items.len());
+    let mut results: Vec<ItemResult> = Vec::with_capacity(items.len());
+    // Position of every accepted dataframe in `results`
+    let mut accepted_indices: Vec<usize> = Vec::with_capacity(items.len());
+
+    // Scoped, the lock must not be held across an await
+    {
+        let schemas: RwLockReadGuard<Schemas> = schemas().read();
+
+        for (index, item) in items.into_iter().enumerate() {
+            let status: ItemStatus = match item {
+                Ok(dataframe) if !identity.owns(dataframe.device_id) => ItemStatus::Rejected {
+                    reason: format!("Device `{}` is not registered to `{}`", dataframe.device_id, identity.username)
+                },
+                Ok(DataFrame { message_id: Some(message_id), .. }) if message_id.len() > MAX_MESSAGE_ID_LENGTH => ItemStatus::Rejected {
+                    reason: format!("message_id must not be longer than {MAX_MESSAGE_ID_LENGTH} characters")
+                },
+                Ok(mut dataframe) => match schemas.validate(&dataframe) {
+                    Ok(()) => {
+                        clocks().adjust(&mut dataframe);
+                        dataframes.push(dataframe);
+                        accepted_indices.push(index);
+                        ItemStatus::Accepted
+                    },
+                    Err(reason) => ItemStatus::Rejected { reason }
+                },
+                Err(reason) => ItemStatus::Rejected { reason }
+            };
+
+            results.push(ItemResult { index, status });
+        }
+    }
+
+    let accepted: usize = dataframes.len();
+    let mut duplicates: usize = 0;
+
+    if accepted > 0 {
+        let (response_sender, response_receiver) = oneshot::channel::<Box<dyn message_passing::Response>>();
+
+        if let Err(err) = dispatch(&sender, Message::Bulk(dataframes, response_sender)) {
+            return dispatch_failed(err);
+        }
+
+        // The transaction failed as a whole, nothing was inserted
+        match response_receiver.await {
+            Ok(response) if response.status_code() != StatusCode::OK => return build_response(response.status_code(), response.content()).expect("Implement Logging"),
+            Ok(response) => {
+                // Positions within the accepted dataframes
+                let duplicate_positions: Vec<usize> = serde_json::from_str(&response.content()).unwrap_or_default();
+                duplicates = duplicate_positions.len();
+
+                for position in duplicate_positions {
+                    results[accepted_indices[position]].status = ItemStatus::Duplicate;
+                }
+            },
+            Err(_) => return build_response(StatusCode::BAD_GATEWAY, "Worker timed out".to_string()).expect("Implement Logging")
+        }
+    }
+
+    let summary: Summary = Summary { accepted, duplicates, rejected: results.len() - accepted, results };
+    let status_code: StatusCode = if accepted == 0 { StatusCode::UNPROCESSABLE_ENTITY } else { StatusCode::OK };
+
+    build_response(status_code, serde_json::to_string(&summary).unwrap()).expect("Implement Logging")
+}
+
+/// Splits the body into dataframes. Fails only if the body as a whole is malformed, invalid items are returned as the
+/// reason they were rejected.
+fn parse_items(body: &[u8]) -> Result<Vec<Result<DataFrame, String>>, Error> {
+    let body: &str = std::str::from_utf8(body).map_err(|_| Error::InvalidDataFormat)?.trim_start();
+
+    let to_dataframe = |value: Value| serde_json::from_value::<DataFrame>(value).map_err(|err| err.to_string());
+
+    if body.starts_with('[') {
+        let values: Vec<Value> = serde_json::from_str(body).map_err(|_| Error::InvalidDataFormat)?;
+        Ok(values.into_iter().map(to_dataframe).collect())
+    } else {
+        Ok(body.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_str::<Value>(line).map_err(|err| err.to_string()).and_then(to_dataframe))
+            .collect())
+    }
+}
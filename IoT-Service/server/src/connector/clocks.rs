@@ -0,0 +1,39 @@
+//! Clock offset per device, see [`crate::data_managment::clock`]
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+use crate::data_managment::clock::{clocks, DeviceClock};
+use super::response::build_response;
+
+use std::collections::HashMap;
+
+
+/// Estimated clock offset of every device (GET), or of a single one with `?device_id=`. Like the dataframes themselves,
+/// the clocks of every device are visible to anyone allowed to GET. The estimates are kept in memory only and start
+/// over after a restart.
+pub const CLOCKS_ENDPOINT: &str = "/api/v1/clocks";
+
+pub fn clocks_request(req: &Request<Body>) -> Response<Body> {
+    if req.method() != Method::GET {
+        return build_response(StatusCode::NOT_IMPLEMENTED, "".to_string()).expect("Implement logging");
+    }
+
+    let query: HashMap<String, String> = req.uri().query().and_then(|query| serde_urlencoded::from_str(query).ok()).unwrap_or_default();
+
+    let device_id: Option<i32> = match query.get("device_id").map(|device_id| device_id.parse::<i32>()) {
+        Some(Ok(device_id)) => Some(device_id),
+        Some(Err(_)) => return build_response(StatusCode::BAD_REQUEST, "Couldn't parse query".to_string()).expect("Implement logging"),
+        None => None
+    };
+
+    let devices: Vec<DeviceClock> = clocks().list()
+        .into_iter()
+        .filter(|clock| device_id.is_none_or(|device_id| clock.device_id == device_id))
+        .collect();
+
+    match (device_id, devices.first()) {
+        (Some(device_id), None) => build_response(StatusCode::NOT_FOUND, format!("No dataframe received from device `{device_id}` since startup")).expect("Implement Logging"),
+        (Some(_), Some(clock)) => build_response(StatusCode::OK, serde_json::to_string(clock).unwrap()).expect("Implement Logging"),
+        (None, _) => build_response(StatusCode::OK, serde_json::to_string(&devices).unwrap()).expect("Implement Logging")
+    }
+}
@@ -0,0 +1,112 @@
+//! Export of every dataframe matching a query, without the page size limit of GET. The dataframes are streamed as
+//! newline delimited JSON while they are read from the database, so the first ones arrive right away and neither side
+//! has to hold the whole result. Once it has started, a failed export can't change the status code anymore, so the
+//! connection is closed before the end of the body instead. Clients see an incomplete transfer, never a shorter result.
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use hyper::header::{CONTENT_TYPE, RETRY_AFTER};
+use tokio::sync::{mpsc, oneshot, Semaphore, SemaphorePermit, TryAcquireError};
+use crossbeam::channel::Sender;
+
+use crate::error::Error;
+use crate::config::WorkerConfig;
+use crate::data_managment::filter::Filter;
+use crate::data_managment::schema::schemas;
+use crate::data_managment::dataformat::Query;
+use crate::data_managment::message_passing::{self, ExportChunk, Message};
+use crate::data_managment::worker::dispatch;
+use super::response::build_response;
+use super::middleware::dispatch_failed;
+
+use std::sync::{Arc, OnceLock};
+
+
+/// Takes the same parameters as GET, except for `cursor` and `count`. `limit` is optional and not capped.
+pub const EXPORT_ENDPOINT: &str = "/api/v1/export";
+
+/// Chunks sent by the worker ahead of the client. Once they are all waiting, the worker waits as well.
+const BUFFERED_CHUNKS: usize = 4;
+
+/// Seconds a client turned away for lack of a free slot is asked to wait
+const EXPORT_RETRY_AFTER: u64 = 10;
+
+/// Every running export holds a worker, so only `worker.max_exports` may run at once
+static EXPORT_SLOTS: OnceLock<Semaphore> = OnceLock::new();
+
+/// Has to be called before the first request is served
+pub fn init_export_limit(config: &WorkerConfig) -> Result<(), Error> {
+    let slots: Semaphore = Semaphore::new(config.max_exports);
+
+    if config.max_exports == 0 {
+        slots.close();
+    }
+
+    EXPORT_SLOTS.set(slots).map_err(|_| Error::ConfigError("Export limit is already initialised".to_string()))
+}
+
+fn export_slots() -> &'static Semaphore {
+    EXPORT_SLOTS.get().expect("init_export_limit has to be called before serving requests")
+}
+
+pub async fn export_request(req: &Request<Body>, sender: Arc<Sender<Message>>) -> Response<Body> {
+    if req.method() != Method::GET {
+        return build_response(StatusCode::NOT_IMPLEMENTED, "".to_string()).expect("Implement logging");
+    }
+
+    let filter: Result<Filter, Error> = serde_urlencoded::from_str::<Query>(req.uri().query().unwrap_or_default())
+        .map_err(|_| Error::QueryParsingError)
+        .and_then(|query| Filter::for_export(&query, &schemas().read()));
+
+    let filter: Filter = match filter {
+        Ok(filter) => filter,
+        Err(Error::InvalidFilter(reason)) => return build_response(StatusCode::BAD_REQUEST, reason).expect("Implement logging"),
+        Err(_) => return build_response(StatusCode::BAD_REQUEST, "Couldn't parse query".to_string()).expect("Implement logging")
+    };
+
+    let slot: SemaphorePermit<'static> = match export_slots().try_acquire() {
+        Ok(slot) => slot,
+        Err(TryAcquireError::NoPermits) => return Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(RETRY_AFTER, EXPORT_RETRY_AFTER)
+            .body(Body::from("Too many exports running, try again later"))
+            .expect("Implement Logging"),
+        Err(TryAcquireError::Closed) => return build_response(StatusCode::SERVICE_UNAVAILABLE, "Exports are disabled".to_string()).expect("Implement Logging")
+    };
+
+    let (response_sender, response_receiver) = oneshot::channel::<Box<dyn message_passing::Response>>();
+    let (chunk_sender, mut chunk_receiver) = mpsc::channel::<ExportChunk>(BUFFERED_CHUNKS);
+
+    if let Err(err) = dispatch(&sender, Message::Export(filter, response_sender, chunk_sender, slot)) {
+        return dispatch_failed(err);
+    }
+
+    match response_receiver.await {
+        Ok(response) if response.status_code() == StatusCode::OK => {},
+        Ok(response) => return build_response(response.status_code(), response.content()).expect("Implement Logging"),
+        Err(_) => return build_response(StatusCode::BAD_GATEWAY, "Worker timed out".to_string()).expect("Implement Logging")
+    }
+
+    let (mut body_sender, body) = Body::channel();
+
+    // Dropping the receiver once the client is gone stops the worker
+    tokio::spawn(async move {
+        while let Some(chunk) = chunk_receiver.recv().await {
+            match chunk {
+                ExportChunk::Data(chunk) => if body_sender.send_data(chunk).await.is_err() {
+                    return;
+                },
+                ExportChunk::End => return,
+                ExportChunk::Failed(_) => break
+            }
+        }
+
+        // Failed, or the worker gave up without saying so
+        body_sender.abort();
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/x-ndjson")
+        .body(body)
+        .expect("Implement Logging")
+}
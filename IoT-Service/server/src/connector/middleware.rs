@@ -5,8 +5,8 @@ use hyper::{
     Request,
     Response,
     StatusCode,
-    body::HttpBody,
-    header::HeaderValue
+    body::Bytes,
+    header::{HeaderMap, HeaderName, AUTHORIZATION, RETRY_AFTER}
 };
 
 use serde_json;
@@ -18,141 +18,402 @@ use crossbeam::channel::Sender;
 
 use crate::error::Error;
 use super::response::build_response;
-use crate::util::load_authentification_data;
-use crate::data_managment::dataformat::{DataFrame, Query};
+use super::aggregate::{aggregate_request, AGGREGATE_ENDPOINT};
+use super::body::{payload_too_large, read_body};
+use super::bulk::{bulk_request, BULK_ENDPOINT};
+use super::clocks::{clocks_request, CLOCKS_ENDPOINT};
+use super::export::{export_request, EXPORT_ENDPOINT};
+use super::admin::{keys_request, lockouts_request, metrics_request, schemas_request, KEYS_ENDPOINT, LOCKOUTS_ENDPOINT, METRICS_ENDPOINT, SCHEMAS_ENDPOINT};
+use crate::config::AuthConfig;
+use crate::authentification::api_keys::ApiKeys;
+use crate::authentification::lockout::Lockouts;
+use crate::authentification::devices::DeviceRegistry;
+use crate::authentification::signature::{DeviceSecrets, NonceCache, SignedRequest, NONCE_CACHE_CAPACITY};
+use crate::authentification::credentials::{Credentials, Identity};
+use crate::data_managment::clock::clocks;
+use crate::data_managment::schema::schemas;
+use crate::data_managment::filter::Filter;
+use crate::data_managment::page::{max_page_size, PageRequest};
+use crate::data_managment::dataformat::{DataFrame, Query, MAX_MESSAGE_ID_LENGTH};
 use crate::data_managment::message_passing::{self, Message};
+use crate::data_managment::worker::dispatch;
 
 use std::str;
-use std::sync::LazyLock;
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 
 
-const BASIC_LENGTH: usize = "Basic ".len();
-static AUTHENTIFICATION: LazyLock<HashMap<String, String>> = LazyLock::new(|| {
-    load_authentification_data(".env")
-});
+const X_API_KEY: &str = "x-api-key";
 
-pub fn auth(auth_header: &HeaderValue) -> Result<bool, Error> {
-    if let Ok(auth_header) = auth_header.to_str() {
-        if auth_header.starts_with("Basic ") {
-            let base_64_encoded: &str = auth_header.get(BASIC_LENGTH..auth_header.len()).unwrap(); // This is safe, as the previous line gurantees that the string is at least of length "Basic "
-            let decoded_auth_header: Vec<u8> = BASE64_STANDARD.decode(base_64_encoded).map_err(|_| Error::DecodeError)?;
+static STORES: OnceLock<Stores> = OnceLock::new();
 
-            let mut decoded_auth_header = decoded_auth_header.split(|elem| *elem == b':');
+const PREFER: HeaderName = HeaderName::from_static("prefer");
+/// Alternative to `message_id` in the body, for clients that can't change the payload
+const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
 
-            let username: &str = str::from_utf8(decoded_auth_header.next().ok_or_else(|| Error::InvalidAuthentificationFormat)?).map_err(|_| Error::ConversionError)?;
-            let unchecked_password: &str = str::from_utf8(decoded_auth_header.next().ok_or_else(|| Error::InvalidAuthentificationFormat)?).map_err(|_| Error::ConversionError)?;
+/// Everything needed to authenticate a request, loaded once at startup by [`init_authentification`]
+pub(crate) struct Stores {
+    pub(crate) credentials: Credentials,
+    pub(crate) devices: DeviceRegistry,
+    pub(crate) device_secrets: DeviceSecrets,
+    pub(crate) nonces: Mutex<NonceCache>,
+    pub(crate) lockouts: Mutex<Lockouts>,
+    /// Keys are issued and revoked at runtime through the admin endpoint, hence the lock
+    pub(crate) api_keys: RwLock<ApiKeys>,
+    pub(crate) api_keys_path: String
+}
+
+/// Loads the credential store, device registry, device secrets and API keys. Has to be called before the first request
+/// is served.
+pub fn init_authentification(config: &AuthConfig) -> Result<(), Error> {
+    let stores: Stores = Stores {
+        credentials: Credentials::load(&config.credentials)?,
+        devices: DeviceRegistry::load_or_default(&config.devices)?,
+        device_secrets: DeviceSecrets::load_or_default(&config.device_secrets)?,
+        nonces: Mutex::new(NonceCache::new(NONCE_CACHE_CAPACITY)),
+        lockouts: Mutex::new(Lockouts::default()),
+        api_keys: RwLock::new(ApiKeys::load_or_default(&config.api_keys)?),
+        api_keys_path: config.api_keys.clone()
+    };
+
+    STORES.set(stores).map_err(|_| Error::CredentialStoreError("Authentification is already initialised".to_string()))
+}
+
+pub(crate) fn stores() -> &'static Stores {
+    STORES.get().expect("init_authentification has to be called before serving requests")
+}
+
+/// Returns the identity of the caller, or `None` if the credentials don't match.
+/// Supports `Authorization: Basic`, `Authorization: Bearer <api key>`, `X-Api-Key: <api key>` and
+/// `Authorization: HMAC-SHA256 ...` (see [`crate::authentification::signature`]). Signed requests cover the body, so once
+/// their header checks out it is read here, up to `server.max_body_size`, and put back into the request afterwards.
+///
+/// Without any of these headers, the common name of a verified TLS client certificate is treated like a Basic auth
+/// username, so role and devices come from the same stores.
+///
+/// Failed attempts are counted per claimed username and per source IP. Once either is locked out, the credentials
+/// aren't checked at all and [`Error::LockedOut`] is returned instead.
+pub async fn auth(req: &mut Request<Body>, client_common_name: Option<&str>, remote_ip: IpAddr) -> Result<Option<Identity>, Error> {
+    let username: Option<String> = claimed_username(req.headers());
 
+    if let Some(retry_after) = stores().lockouts.lock().expect("Implement Logging | lockouts poisoned").retry_after(username.as_deref(), Some(remote_ip)) {
+        return Err(Error::LockedOut(retry_after));
+    }
+
+    let result: Result<Option<Identity>, Error> = verify_credentials(req, client_common_name).await;
+
+    match &result {
+        Ok(Some(identity)) => stores().lockouts.lock().expect("Implement Logging | lockouts poisoned").record_success(&identity.username),
+        Ok(None)
+        | Err(Error::DecodeError)
+        | Err(Error::ConversionError)
+        | Err(Error::InvalidAuthentificationFormat)
+        | Err(Error::StaleSignature)
+        | Err(Error::ReplayedSignature) => stores().lockouts.lock().expect("Implement Logging | lockouts poisoned").record_failure(username.as_deref(), Some(remote_ip)),
+        _ => {}
+    }
+
+    result
+}
 
-            Ok(AUTHENTIFICATION.get(username).map_or(false, |password: &String| unchecked_password == password))
+/// Username the caller claims to be, before any verification. Used to count failed attempts.
+fn claimed_username(headers: &HeaderMap) -> Option<String> {
+    let auth_header: &str = headers.get(AUTHORIZATION)?.to_str().ok()?;
+
+    if let Some(base_64_encoded) = auth_header.strip_prefix("Basic ") {
+        let decoded: Vec<u8> = BASE64_STANDARD.decode(base_64_encoded).ok()?;
+        let username: &[u8] = decoded.split(|elem| *elem == b':').next()?;
+
+        str::from_utf8(username).ok().map(str::to_string)
+    } else {
+        auth_header.strip_prefix("HMAC-SHA256 ")?.trim().split(':').next().map(str::to_string)
+    }
+}
+
+async fn verify_credentials(req: &mut Request<Body>, client_common_name: Option<&str>) -> Result<Option<Identity>, Error> {
+    let headers = req.headers();
+
+    if let Some(auth_header) = headers.get(AUTHORIZATION) {
+        let auth_header: &str = auth_header.to_str().map_err(|_| Error::FailedHeaderConversion)?;
+
+        if let Some(base_64_encoded) = auth_header.strip_prefix("Basic ") {
+            basic_auth(base_64_encoded)
+        } else if let Some(api_key) = auth_header.strip_prefix("Bearer ") {
+            Ok(stores().api_keys.read().expect("Implement Logging | API key store poisoned").verify(api_key.trim()))
+        } else if let Some(signature) = auth_header.strip_prefix("HMAC-SHA256 ") {
+            let signature: String = signature.to_string();
+            signature_auth(req, &signature).await
         } else {
-            Err(Error::InvalidAuthentificationFormat) // If the authentification header doesn't follow basic auth.
+            Err(Error::InvalidAuthentificationFormat) // If the authentification header follows none of the supported schemes
         }
+    } else if let Some(api_key) = headers.get(X_API_KEY) {
+        let api_key: &str = api_key.to_str().map_err(|_| Error::FailedHeaderConversion)?;
+        Ok(stores().api_keys.read().expect("Implement Logging | API key store poisoned").verify(api_key.trim()))
+    } else if let Some(username) = client_common_name {
+        Ok(stores().credentials.identity(username).map(|mut identity| {
+            identity.devices = stores().devices.devices_of(username);
+            identity
+        }))
     } else {
-        Err(Error::FailedHeaderConversion)
+        Err(Error::AuthentificationMissing)
     }
 }
 
+async fn signature_auth(req: &mut Request<Body>, signature: &str) -> Result<Option<Identity>, Error> {
+    // The header is checked first, so unsigned or stale requests are turned away before their body is read
+    let signed: SignedRequest = match stores().device_secrets.signed_request(signature) {
+        Ok(signed) => signed,
+        Err(Error::InvalidSignature) => return Ok(None),
+        Err(err) => return Err(err)
+    };
 
-pub async fn serve_request(mut req: Request<Body>, sender_channel: Arc<Sender<Message>>) -> Response<Body> {
-    match req.method() {
-        &Method::POST => post_request(req.body_mut(), sender_channel).await,
-        &Method::GET => get_request(req.uri(), sender_channel).await,
-        &Method::DELETE => delete_request(req.uri(), sender_channel).await,
-        &Method::PUT => put_request(&req.uri().clone(),req.body_mut(), sender_channel).await,
-        _ => build_response(StatusCode::NOT_IMPLEMENTED, "".to_string()).expect("Implement logging")
+    let body: Bytes = read_body(req.body_mut()).await?;
+
+    let method: String = req.method().to_string();
+    let path_and_query: String = req.uri().path_and_query().map_or(req.uri().path().to_string(), |path_and_query| path_and_query.to_string());
+
+    let verified: Result<String, Error> = {
+        let mut nonces = stores().nonces.lock().expect("Implement Logging | nonce cache poisoned");
+        signed.verify(&method, &path_and_query, &body, &mut nonces)
+    };
+
+    *req.body_mut() = Body::from(body);
+
+    match verified {
+        Ok(username) => Ok(stores().credentials.identity(&username).map(|mut identity| {
+            identity.devices = stores().devices.devices_of(&username);
+            identity
+        })),
+        Err(Error::InvalidSignature) => Ok(None),
+        Err(err) => Err(err)
     }
 }
 
-async fn parse_body<T: for <'a> Deserialize<'a>>(data: &mut Body) -> Result<T, Error> {
-    if let Ok(body) = data.collect().await {
-        let data: String = body.to_bytes().iter().map(|elem| *elem as char).collect::<String>();
-        serde_json::from_str::<T>(&data).map_err(|_| Error::InvalidDataFormat)
-    } else {
-        Err(Error::TransmissionError)
-    }
+fn basic_auth(base_64_encoded: &str) -> Result<Option<Identity>, Error> {
+    let decoded_auth_header: Vec<u8> = BASE64_STANDARD.decode(base_64_encoded).map_err(|_| Error::DecodeError)?;
+
+    // Passwords may contain ':', only the first one separates the username
+    let mut decoded_auth_header = decoded_auth_header.splitn(2, |elem| *elem == b':');
+
+    let username: &str = str::from_utf8(decoded_auth_header.next().ok_or(Error::InvalidAuthentificationFormat)?).map_err(|_| Error::ConversionError)?;
+    let unchecked_password: &str = str::from_utf8(decoded_auth_header.next().ok_or(Error::InvalidAuthentificationFormat)?).map_err(|_| Error::ConversionError)?;
+
+    Ok(stores().credentials.verify(username, unchecked_password).map(|mut identity| {
+        identity.devices = stores().devices.devices_of(username);
+        identity
+    }))
 }
 
-async fn post_request(data: &mut Body, sender: Arc<Sender<Message>>) -> Response<Body> {
-    match parse_body::<DataFrame>(data).await {
-        Ok(dataframe) => {
-            if let Ok(_) = sender.send(Message::Post(dataframe)) {
-                build_response(StatusCode::OK, "".to_string()).expect("Implement Logging")
-            } else {
-                build_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed Message Passing to Worker".to_string()).expect("Implement Logging")
-            }
+
+pub async fn serve_request(mut req: Request<Body>, sender_channel: Arc<Sender<Message>>, identity: &Identity) -> Response<Body> {
+    match req.uri().path() {
+        KEYS_ENDPOINT => return keys_request(req, identity).await,
+        LOCKOUTS_ENDPOINT => return lockouts_request(req, identity),
+        METRICS_ENDPOINT => return metrics_request(req, identity, &sender_channel),
+        SCHEMAS_ENDPOINT => return schemas_request(req, identity).await,
+        _ => {}
+    }
+
+    // Authorization has to happen before any message reaches the worker
+    if !identity.role.permits(req.method()) {
+        return build_response(StatusCode::FORBIDDEN, format!("Role `{}` is not allowed to {}", identity.role, req.method())).expect("Implement Logging");
+    }
+
+    if req.uri().path() == CLOCKS_ENDPOINT {
+        return clocks_request(&req);
+    }
+
+    if req.uri().path() == EXPORT_ENDPOINT {
+        return export_request(&req, sender_channel).await;
+    }
+
+    if req.uri().path() == AGGREGATE_ENDPOINT {
+        return aggregate_request(&req, sender_channel).await;
+    }
+
+    if req.uri().path() == BULK_ENDPOINT {
+        return match *req.method() {
+            Method::POST => bulk_request(req.body_mut(), sender_channel, identity).await,
+            _ => build_response(StatusCode::NOT_IMPLEMENTED, "".to_string()).expect("Implement logging")
+        };
+    }
+
+    match *req.method() {
+        Method::POST => {
+            let respond_async: bool = prefers_respond_async(req.headers());
+            let idempotency_key: Option<String> = req.headers().get(IDEMPOTENCY_KEY).and_then(|key| key.to_str().ok()).map(str::to_string);
+
+            post_request(req.body_mut(), sender_channel, identity, respond_async, idempotency_key).await
         },
-        Err(err) => match err {
+        Method::GET => get_request(req.uri(), sender_channel).await,
+        Method::DELETE => delete_request(req.uri(), sender_channel, identity).await,
+        Method::PUT => put_request(&req.uri().clone(),req.body_mut(), sender_channel, identity).await,
+        _ => build_response(StatusCode::NOT_IMPLEMENTED, "".to_string()).expect("Implement logging")
+    }
+}
+
+/// Fails with [`Error::PayloadTooLarge`] for bodies larger than `server.max_body_size`, see [`read_body`]
+pub(crate) async fn parse_body<T: for <'a> Deserialize<'a>>(data: &mut Body) -> Result<T, Error> {
+    let body: Bytes = read_body(data).await?;
+    let data: String = body.iter().map(|elem| *elem as char).collect::<String>();
+    serde_json::from_str::<T>(&data).map_err(|_| Error::InvalidDataFormat)
+}
+
+/// `Prefer: respond-async` (RFC 7240) asks to answer with 202 as soon as the dataframe is queued, instead of waiting
+/// for the insert
+fn prefers_respond_async(headers: &HeaderMap) -> bool {
+    headers.get_all(PREFER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|preference| preference.trim().eq_ignore_ascii_case("respond-async"))
+}
+
+async fn post_request(data: &mut Body, sender: Arc<Sender<Message>>, identity: &Identity, respond_async: bool, idempotency_key: Option<String>) -> Response<Body> {
+    let mut dataframe: DataFrame = match parse_body::<DataFrame>(data).await {
+        Ok(dataframe) => dataframe,
+        Err(err) => return match err {
             Error::InvalidDataFormat => build_response(StatusCode::BAD_REQUEST, "Invalid data format".to_string()).expect("Implement logging"),
             Error::TransmissionError => build_response(StatusCode::BAD_REQUEST, "Data transmission failed".to_string()).expect("Implement logging"),
+            Error::PayloadTooLarge => payload_too_large(),
             _ => panic!("ATM undefined behaviour, open for future extension")
         }
+    };
+
+    match (idempotency_key, &dataframe.message_id) {
+        (Some(key), Some(message_id)) if key != *message_id => {
+            return build_response(StatusCode::BAD_REQUEST, "message_id and Idempotency-Key header differ".to_string()).expect("Implement logging");
+        },
+        (Some(key), None) => dataframe.message_id = Some(key),
+        _ => {}
+    }
+
+    if dataframe.message_id.as_ref().is_some_and(|message_id| message_id.len() > MAX_MESSAGE_ID_LENGTH) {
+        return build_response(StatusCode::BAD_REQUEST, format!("message_id must not be longer than {MAX_MESSAGE_ID_LENGTH} characters")).expect("Implement logging");
+    }
+
+    if !identity.owns(dataframe.device_id) {
+        return forbidden_device(identity, dataframe.device_id);
+    }
+
+    if let Err(reason) = schemas().read().validate(&dataframe) {
+        return build_response(StatusCode::BAD_REQUEST, reason).expect("Implement logging");
+    }
+
+    clocks().observe(&mut dataframe);
+
+    if respond_async {
+        return match dispatch(&sender, Message::Post(dataframe, None)) {
+            Ok(()) => build_response(StatusCode::ACCEPTED, "".to_string()).expect("Implement Logging"),
+            Err(err) => dispatch_failed(err)
+        };
+    }
+
+    // Bi-directional channel to relay the outcome of the insert back
+    let (response_sender, response_receiver) = oneshot::channel::<Box<dyn message_passing::Response>>();
+
+    if let Err(err) = dispatch(&sender, Message::Post(dataframe, Some(response_sender))) {
+        return dispatch_failed(err);
+    }
+
+    match response_receiver.await {
+        Ok(response) => build_response(response.status_code(), response.content()).expect("Implement Logging"),
+        Err(_) => build_response(StatusCode::BAD_GATEWAY, "Worker timed out".to_string()).expect("Implement Logging")
     }
 }
 
 async fn get_request(uri: &Uri, sender: Arc<Sender<Message>>) -> Response<Body> {
-    match extract_query(uri) {
-        Ok(query) => {
+    match extract_query(uri).and_then(|query| Ok((Filter::for_page(&query, &schemas().read(), max_page_size())?, query.count))) {
+        Ok((filter, count)) => {
+            let request: PageRequest = PageRequest { count, link: next_link(uri) };
+
             // Bi-directional channel to relay query back. One shot channels are non blocking, meaning the main thread can continue executing
             let (response_sender, response_receiver) = oneshot::channel::<Box<dyn message_passing::Response>>();
 
-            if let Ok(_) = sender.send(Message::Get(query, response_sender)) {
-                match response_receiver.await {
-                    Ok(response) => build_response(response.status_code(), response.content()).expect("Implement Logging"),
-                    Err(_) => build_response(StatusCode::BAD_GATEWAY, "Worker timed out".to_string()).expect("Implement Logging")
-                }
-            } else {
-                build_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed Message Passing to Worker".to_string()).expect("Implement Logging")
+            if let Err(err) = dispatch(&sender, Message::Get(filter, request, response_sender)) {
+                return dispatch_failed(err);
+            }
+
+            match response_receiver.await {
+                Ok(response) => build_response(response.status_code(), response.content()).expect("Implement Logging"),
+                Err(_) => build_response(StatusCode::BAD_GATEWAY, "Worker timed out".to_string()).expect("Implement Logging")
             }
         },
         Err(err) => match err {
             Error::QueryInvalidAPI => build_response(StatusCode::BAD_REQUEST, "Invalid API. Consider using /api/v1/".to_string()).expect("Implement logging"),
             Error::QueryNotProvided => build_response(StatusCode::BAD_REQUEST, "No Query found. Consider adding one.".to_string()).expect("Implement logging"),
             Error::QueryParsingError => build_response(StatusCode::BAD_REQUEST, "Couldn't parse query".to_string()).expect("Implement logging"),
+            Error::InvalidFilter(reason) => build_response(StatusCode::BAD_REQUEST, reason).expect("Implement logging"),
             _ => build_response(StatusCode::INTERNAL_SERVER_ERROR, "Request introduces undefined behaviour. Request rejected".to_string()).expect("Implement logging")
         }
     }
 }
 
-async fn delete_request(uri: &Uri, sender: Arc<Sender<Message>>) -> Response<Body> {
-    match extract_query(uri) {
-        Ok(query) => {
+async fn delete_request(uri: &Uri, sender: Arc<Sender<Message>>, identity: &Identity) -> Response<Body> {
+    let query: Result<Query, Error> = extract_query(uri);
+
+    if let Some(device_id) = query.as_ref().ok().and_then(|query| query.device_id.iter().find(|device_id| !identity.owns(**device_id))) {
+        return forbidden_device(identity, *device_id);
+    }
+
+    match query.and_then(|query| Filter::for_modification(&query, &schemas().read())) {
+        Ok(filter) => {
             // Bi-directional channel to relay query back. One shot channels are non blocking, meaning the main thread can continue executing
             let (response_sender, response_receiver) = oneshot::channel::<Box<dyn message_passing::Response>>();
 
-            if let Ok(_) = sender.send(Message::Delete(query, response_sender)) {
-                match response_receiver.await {
-                    Ok(response) => build_response(response.status_code(), response.content()).expect("Implement Logging"),
-                    Err(_) => build_response(StatusCode::BAD_GATEWAY, "Worker timed out".to_string()).expect("Implement Logging")
-                }
-            } else {
-                build_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed Message Passing to Worker".to_string()).expect("Implement Logging")
+            if let Err(err) = dispatch(&sender, Message::Delete(filter, response_sender)) {
+                return dispatch_failed(err);
+            }
+
+            match response_receiver.await {
+                Ok(response) => build_response(response.status_code(), response.content()).expect("Implement Logging"),
+                Err(_) => build_response(StatusCode::BAD_GATEWAY, "Worker timed out".to_string()).expect("Implement Logging")
             }
         },
         Err(err) => match err {
             Error::QueryInvalidAPI => build_response(StatusCode::BAD_REQUEST, "Invalid API. Consider using /api/v1/".to_string()).expect("Implement logging"),
             Error::QueryNotProvided => build_response(StatusCode::BAD_REQUEST, "No Query found. Consider adding one.".to_string()).expect("Implement logging"),
             Error::QueryParsingError => build_response(StatusCode::BAD_REQUEST, "Couldn't parse query".to_string()).expect("Implement logging"),
+            Error::InvalidFilter(reason) => build_response(StatusCode::BAD_REQUEST, reason).expect("Implement logging"),
             _ => build_response(StatusCode::INTERNAL_SERVER_ERROR, "Request introduces undefined behaviour. Request rejected".to_string()).expect("Implement logging")
         }
     }
 }
 
-async fn put_request(uri: &Uri, data: &mut Body, sender: Arc<Sender<Message>>) -> Response<Body> {
-    match (extract_query(uri), parse_body::<DataFrame>(data).await) {
+async fn put_request(uri: &Uri, data: &mut Body, sender: Arc<Sender<Message>>, identity: &Identity) -> Response<Body> {
+    let query: Result<Query, Error> = extract_query(uri);
+
+    if let Some(device_id) = query.as_ref().ok().and_then(|query| query.device_id.iter().find(|device_id| !identity.owns(**device_id))) {
+        return forbidden_device(identity, *device_id);
+    }
+
+    match (query, parse_body::<DataFrame>(data).await) {
+        (_, Ok(DataFrame { device_id, .. })) if !identity.owns(device_id) => forbidden_device(identity, device_id),
+        (_, Err(Error::PayloadTooLarge)) => payload_too_large(),
         (Ok(query), Ok(dataframe))=> {
+            let filter: Filter = match Filter::for_modification(&query, &schemas().read()) {
+                Ok(filter) => filter,
+                Err(Error::InvalidFilter(reason)) => return build_response(StatusCode::BAD_REQUEST, reason).expect("Implement logging"),
+                Err(_) => return build_response(StatusCode::BAD_REQUEST, "Couldn't parse query".to_string()).expect("Implement logging")
+            };
+
+            // The rows of the queried devices get the new fields
+            let invalid: Option<String> = query.device_id.iter().find_map(|device_id| schemas().read().of_device(*device_id).validate(&dataframe.fields).err());
+
+            if let Some(reason) = invalid {
+                return build_response(StatusCode::BAD_REQUEST, reason).expect("Implement logging");
+            }
 
             // Bi-directional channel to relay query back. One shot channels are non blocking, meaning the main thread can continue executing
             let (response_sender, response_receiver) = oneshot::channel::<Box<dyn message_passing::Response>>();
 
-            if let Ok(_) = sender.send(Message::Put(query, dataframe, response_sender)) {
-                match response_receiver.await {
-                    Ok(response) => build_response(response.status_code(), response.content()).expect("Implement Logging"),
-                    Err(_) => build_response(StatusCode::BAD_GATEWAY, "Worker timed out".to_string()).expect("Implement Logging")
-                }
-            } else {
-                build_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed Message Passing to Worker".to_string()).expect("Implement Logging")
+            if let Err(err) = dispatch(&sender, Message::Put(filter, dataframe, response_sender)) {
+                return dispatch_failed(err);
+            }
+
+            match response_receiver.await {
+                Ok(response) => build_response(response.status_code(), response.content()).expect("Implement Logging"),
+                Err(_) => build_response(StatusCode::BAD_GATEWAY, "Worker timed out".to_string()).expect("Implement Logging")
             }
         },
         (Err(query_err), Err(parse_err)) => match (query_err, parse_err) {
@@ -167,6 +428,39 @@ async fn put_request(uri: &Uri, data: &mut Body, sender: Arc<Sender<Message>>) -
     }
 }
 
+/// Response for a message the worker queue didn't accept
+pub(crate) fn dispatch_failed(err: Error) -> Response<Body> {
+    match err {
+        Error::QueueFull => Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(RETRY_AFTER, 1)
+            .body(Body::from("Too many pending requests, try again later"))
+            .expect("Implement Logging"),
+        _ => build_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed Message Passing to Worker".to_string()).expect("Implement Logging")
+    }
+}
+
+pub(crate) fn forbidden_device(identity: &Identity, device_id: i32) -> Response<Body> {
+    build_response(StatusCode::FORBIDDEN, format!("Device `{device_id}` is not registered to `{}`", identity.username)).expect("Implement Logging")
+}
+
+/// The request URI without its cursor, ready for the cursor of the next page to be appended
+fn next_link(uri: &Uri) -> String {
+    let params: Vec<(String, String)> = serde_urlencoded::from_str::<Vec<(String, String)>>(uri.query().unwrap_or_default())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(key, _)| key != "cursor")
+        .collect();
+
+    let mut query: String = serde_urlencoded::to_string(&params).expect("Implement Logging | Failed to encode query");
+
+    if !query.is_empty() {
+        query.push('&');
+    }
+
+    format!("{}?{query}cursor=", uri.path())
+}
+
 fn extract_query(uri: &Uri) -> Result<Query, Error> {
     // Check if the URI path matches the expected endpoint
 
@@ -1,2 +1,9 @@
+pub mod admin;
+pub mod aggregate;
+pub mod body;
+pub mod bulk;
+pub mod clocks;
+pub mod export;
 pub mod middleware;
 pub mod response;
+pub mod tls;
@@ -0,0 +1,150 @@
+use rustls::ServerConfig;
+use rustls::RootCertStore;
+use rustls::server::WebPkiClientVerifier;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::error::Error;
+
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::time::{Duration, SystemTime};
+use std::sync::{Arc, RwLock};
+
+
+/// How often the certificate files are checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Paths of the PEM files used for HTTPS. Without a client CA, clients authenticate via the `Authorization` header only.
+#[derive(Debug, Clone)]
+pub struct TlsSettings {
+    pub cert_path: String,
+    pub key_path: String,
+    /// CA used to verify client certificates. Clients without a certificate are still accepted.
+    pub client_ca_path: Option<String>
+}
+
+impl TlsSettings {
+    pub fn server_config(&self) -> Result<Arc<ServerConfig>, Error> {
+        let certs: Vec<CertificateDer<'static>> = load_certs(&self.cert_path)?;
+        let key: PrivateKeyDer<'static> = load_key(&self.key_path)?;
+
+        let builder = ServerConfig::builder();
+
+        let builder = match &self.client_ca_path {
+            Some(client_ca_path) => {
+                let mut roots: RootCertStore = RootCertStore::empty();
+
+                for cert in load_certs(client_ca_path)? {
+                    roots.add(cert).map_err(|err| Error::TlsConfigError(format!("{client_ca_path}: {err}")))?;
+                }
+
+                let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                    .allow_unauthenticated()
+                    .build()
+                    .map_err(|err| Error::TlsConfigError(format!("{client_ca_path}: {err}")))?;
+
+                builder.with_client_cert_verifier(verifier)
+            },
+            None => builder.with_no_client_auth()
+        };
+
+        let mut config: ServerConfig = builder
+            .with_single_cert(certs, key)
+            .map_err(|err| Error::TlsConfigError(format!("{}: {err}", self.cert_path)))?;
+
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(Arc::new(config))
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        [Some(&self.cert_path), Some(&self.key_path), self.client_ca_path.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+            .collect()
+    }
+}
+
+/// Hands out a [`TlsAcceptor`] for the current certificate. The certificate is reloaded in the background whenever one
+/// of the files changes, connections that are already established keep the certificate they started with.
+#[derive(Clone)]
+pub struct ReloadingTlsAcceptor {
+    acceptor: Arc<RwLock<TlsAcceptor>>
+}
+
+impl ReloadingTlsAcceptor {
+    /// Loads the certificate and spawns the reload task. Must be called from within a tokio runtime.
+    pub fn new(settings: TlsSettings) -> Result<Self, Error> {
+        let acceptor: Arc<RwLock<TlsAcceptor>> = Arc::new(RwLock::new(TlsAcceptor::from(settings.server_config()?)));
+
+        let reloading: Arc<RwLock<TlsAcceptor>> = acceptor.clone();
+        tokio::spawn(async move {
+            let mut last_modified: Vec<Option<SystemTime>> = settings.modified();
+            let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+
+            loop {
+                interval.tick().await;
+
+                let modified: Vec<Option<SystemTime>> = settings.modified();
+
+                if modified == last_modified {
+                    continue;
+                }
+
+                // A half written file fails to parse, in which case the old certificate stays in use and the next tick retries
+                match settings.server_config() {
+                    Ok(config) => {
+                        *reloading.write().expect("Implement Logging | TLS acceptor poisoned") = TlsAcceptor::from(config);
+                        last_modified = modified;
+                        println!("Reloaded TLS certificate {}", settings.cert_path);
+                    },
+                    Err(err) => eprintln!("Failed to reload TLS certificate: {err}")
+                }
+            }
+        });
+
+        Ok(Self { acceptor })
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        self.acceptor.read().expect("Implement Logging | TLS acceptor poisoned").clone()
+    }
+}
+
+/// Common name of the verified client certificate, if the client presented one
+pub fn client_common_name(stream: &TlsStream<TcpStream>) -> Option<String> {
+    let (_, connection) = stream.get_ref();
+    let certificate: &CertificateDer = connection.peer_certificates()?.first()?;
+
+    let (_, certificate) = X509Certificate::from_der(certificate).ok()?;
+    let common_name: Option<String> = certificate.subject().iter_common_name().next()?.as_str().ok().map(str::to_string);
+
+    common_name
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let file: File = File::open(path).map_err(|err| Error::TlsConfigError(format!("{path}: {err}")))?;
+
+    let certs: Vec<CertificateDer<'static>> = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<_, _>>()
+        .map_err(|err| Error::TlsConfigError(format!("{path}: {err}")))?;
+
+    if certs.is_empty() {
+        Err(Error::TlsConfigError(format!("{path}: no certificate found")))
+    } else {
+        Ok(certs)
+    }
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, Error> {
+    let file: File = File::open(path).map_err(|err| Error::TlsConfigError(format!("{path}: {err}")))?;
+
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|err| Error::TlsConfigError(format!("{path}: {err}")))?
+        .ok_or_else(|| Error::TlsConfigError(format!("{path}: no private key found")))
+}
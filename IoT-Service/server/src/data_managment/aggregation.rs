@@ -0,0 +1,216 @@
+//! Statistics per device and time bucket, computed by the database instead of the client:
+//!
+//! - `bucket=15m` sets the size of the buckets, a number followed by `s`, `m`, `h` or `d`. Buckets are aligned to the
+//!   unix epoch in UTC, so `1d` buckets start at midnight UTC.
+//! - `fields=temp,rpm` selects the measurements, by default every number in the schemas of the devices
+//! - `percentiles=50,95,99` adds percentiles between 0 and 100, interpolated between the closest values
+//!
+//! `device_id`, `time_start` and `time_end` are required, `filter` narrows down the dataframes just like for GET.
+
+use serde::{Serialize, Deserialize};
+
+use crate::error::Error;
+use super::filter::Filter;
+use super::dataformat::Query;
+use super::schema::{FieldType, Schema, Schemas};
+use super::timestamp::Timestamp;
+
+use std::collections::BTreeMap;
+
+
+/// Most buckets per device a single request may ask for
+pub const MAX_BUCKETS: i64 = 10_000;
+
+/// The parameters of a request besides those of [`Query`]
+#[derive(Debug, Deserialize)]
+pub struct AggregationQuery {
+    pub bucket: String,
+    pub fields: Option<String>,
+    pub percentiles: Option<String>
+}
+
+#[derive(Debug)]
+pub struct Aggregation {
+    pub filter: Filter,
+    pub bucket_seconds: f64,
+    pub fields: Vec<String>,
+    /// As given by the client and as fraction between 0 and 1
+    pub percentiles: Vec<(String, f64)>
+}
+
+#[derive(Debug, Serialize)]
+pub struct Bucket {
+    pub device_id: i32,
+    pub start: Timestamp,
+    /// Dataframes in the bucket
+    pub count: i64,
+    pub fields: BTreeMap<String, FieldStatistics>
+}
+
+/// Statistics of one measurement in a bucket. Dataframes without a number for it are left out.
+#[derive(Debug, Serialize)]
+pub struct FieldStatistics {
+    pub count: i64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub percentiles: BTreeMap<String, Option<f64>>
+}
+
+impl Aggregation {
+    pub fn from_query(aggregation: &AggregationQuery, query: &Query, schemas: &Schemas) -> Result<Self, Error> {
+        if query.order.is_some() || query.limit.is_some() || query.cursor.is_some() || query.count || query.time_now.is_some() {
+            return Err(Error::InvalidFilter("order, limit, cursor, count and time_now are not supported for aggregations".to_string()));
+        }
+
+        if query.device_id.is_empty() {
+            return Err(Error::InvalidFilter("device_id is required".to_string()));
+        }
+
+        let (Some(start), Some(end)) = (query.time_start, query.time_end) else {
+            return Err(Error::InvalidFilter("time_start and time_end are required".to_string()));
+        };
+
+        let bucket_seconds: i64 = parse_bucket(&aggregation.bucket)?;
+
+        if (end - start).num_seconds() / bucket_seconds >= MAX_BUCKETS {
+            return Err(Error::InvalidFilter(format!("At most {MAX_BUCKETS} buckets per device, use larger buckets or a shorter window")));
+        }
+
+        let devices: Vec<&Schema> = query.device_id.iter().map(|device_id| schemas.of_device(*device_id)).collect();
+        let numeric = |name: &str| devices.iter().any(|schema| schema.field(name).is_some_and(|field| matches!(field.kind, FieldType::Integer | FieldType::Float)));
+
+        let fields: Vec<String> = match &aggregation.fields {
+            Some(fields) => fields.split(',')
+                .map(str::trim)
+                .map(|name| match numeric(name) {
+                    true => Ok(name.to_string()),
+                    false => Err(Error::InvalidFilter(format!("`{name}` is no number in the schemas of the devices")))
+                })
+                .collect::<Result<_, _>>()?,
+            None => {
+                let mut fields: Vec<String> = Vec::new();
+
+                for field in devices.iter().flat_map(|schema| &schema.fields) {
+                    if matches!(field.kind, FieldType::Integer | FieldType::Float) && !fields.contains(&field.name) {
+                        fields.push(field.name.clone());
+                    }
+                }
+
+                fields
+            }
+        };
+
+        if fields.is_empty() {
+            return Err(Error::InvalidFilter("The schemas of the devices have no numbers to aggregate".to_string()));
+        }
+
+        let percentiles: Vec<(String, f64)> = match &aggregation.percentiles {
+            Some(percentiles) => percentiles.split(',')
+                .map(str::trim)
+                .map(|percentile| match percentile.parse::<f64>() {
+                    Ok(value) if (0.0..=100.0).contains(&value) => Ok((percentile.to_string(), value / 100.0)),
+                    _ => Err(Error::InvalidFilter(format!("Invalid percentile `{percentile}`, expected a number between 0 and 100")))
+                })
+                .collect::<Result<_, _>>()?,
+            None => Vec::new()
+        };
+
+        Ok(Self { filter: Filter::from_query(query, schemas)?, bucket_seconds: bucket_seconds as f64, fields, percentiles })
+    }
+}
+
+/// `90s`, `15m`, `1h` or `7d` in seconds
+fn parse_bucket(bucket: &str) -> Result<i64, Error> {
+    let bucket: &str = bucket.trim();
+    let invalid = || Error::InvalidFilter(format!("Invalid bucket `{bucket}`, expected e.g. 30s, 15m, 1h or 1d"));
+
+    let unit: i64 = match bucket.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        _ => return Err(invalid())
+    };
+
+    // The unit is a single byte
+    match bucket[..bucket.len() - 1].parse::<i64>() {
+        Ok(count) if count > 0 => count.checked_mul(unit).ok_or_else(invalid),
+        _ => Err(invalid())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+
+
+    fn reason<T: std::fmt::Debug>(result: Result<T, Error>) -> String {
+        match result {
+            Err(Error::InvalidFilter(reason)) => reason,
+            other => panic!("expected an invalid aggregation, got {other:?}")
+        }
+    }
+
+    fn aggregation(bucket: &str, percentiles: Option<&str>) -> Result<Aggregation, Error> {
+        let aggregation: AggregationQuery = AggregationQuery { bucket: bucket.to_string(), fields: None, percentiles: percentiles.map(str::to_string) };
+        let query: Query = Query {
+            device_id: vec![1],
+            time_start: DateTime::from_timestamp(0, 0),
+            time_end: DateTime::from_timestamp(24 * 60 * 60, 0),
+            ..Query::default()
+        };
+
+        Aggregation::from_query(&aggregation, &query, &Schemas::default())
+    }
+
+    #[test]
+    fn buckets_in_every_unit() {
+        assert_eq!(parse_bucket("90s").unwrap(), 90);
+        assert_eq!(parse_bucket("15m").unwrap(), 15 * 60);
+        assert_eq!(parse_bucket("1h").unwrap(), 60 * 60);
+        assert_eq!(parse_bucket(" 7d ").unwrap(), 7 * 24 * 60 * 60);
+    }
+
+    #[test]
+    fn invalid_buckets_are_rejected() {
+        for bucket in ["", "s", "0s", "0d", "-1h", "+-1h", "1", "1w", "1.5h", "h1", "1 h", "1H", "1é"] {
+            assert!(parse_bucket(bucket).is_err(), "{bucket:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn overflowing_buckets_are_rejected() {
+        assert_eq!(parse_bucket(&format!("{}s", i64::MAX)).unwrap(), i64::MAX);
+        assert!(parse_bucket(&format!("{}d", i64::MAX / 60)).is_err());
+        assert!(parse_bucket("99999999999999999999s").is_err());
+    }
+
+    #[test]
+    fn bucket_count_is_limited() {
+        assert_eq!(aggregation("1d", None).unwrap().bucket_seconds, 86_400.0);
+        assert_eq!(reason(aggregation("1s", None)), format!("At most {MAX_BUCKETS} buckets per device, use larger buckets or a shorter window"));
+    }
+
+    #[test]
+    fn percentiles_within_bounds() {
+        let aggregation: Aggregation = aggregation("1h", Some("0, 50,99.5,100")).unwrap();
+
+        assert_eq!(aggregation.percentiles, [("0".to_string(), 0.0), ("50".to_string(), 0.5), ("99.5".to_string(), 0.995), ("100".to_string(), 1.0)]);
+    }
+
+    #[test]
+    fn percentiles_out_of_bounds_are_rejected() {
+        for percentile in ["-0.1", "100.1", "NaN", "inf", "", "p95"] {
+            assert_eq!(
+                reason(aggregation("1h", Some(percentile))),
+                format!("Invalid percentile `{percentile}`, expected a number between 0 and 100"),
+                "{percentile:?}"
+            );
+        }
+    }
+}
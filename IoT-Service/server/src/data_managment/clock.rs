@@ -0,0 +1,139 @@
+//! Devices without a synchronised clock, e.g. an ESP32 without RTC, send timestamps that can be off by anything from
+//! seconds to decades. Every dataframe is therefore stamped with the time the server received it, and the difference
+//! between both, the skew, is used to estimate how far the clock of each device is off.
+//!
+//! Only dataframes posted one by one update the estimate. Bulk uploads are usually buffered readings, whose skew is
+//! mostly the time they spent in the buffer. Once the estimated offset of a device exceeds `clock.skew_threshold_ms`,
+//! its dataframes are flagged or corrected depending on `clock.skew_action`. Dataframes without a timestamp of their
+//! own were stamped by the server and say nothing about the device clock, so they are left alone.
+//!
+//! The estimates are kept in memory only. After a restart the estimate of every device starts over from its next
+//! dataframe.
+
+use chrono::TimeDelta;
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::config::{ClockConfig, SkewAction};
+use super::dataformat::DataFrame;
+use super::timestamp::Timestamp;
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+
+static CLOCKS: OnceLock<Clocks> = OnceLock::new();
+
+/// Weight of the latest skew in the estimated offset. Smooths out network latency.
+const SMOOTHING: f64 = 0.2;
+
+pub struct Clocks {
+    threshold_ms: i64,
+    action: SkewAction,
+    devices: Mutex<HashMap<i32, DeviceClock>>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceClock {
+    pub device_id: i32,
+    /// Estimated milliseconds the device clock is behind the server, negative if it is ahead
+    pub offset_ms: i64,
+    /// Skew of the latest dataframe posted one by one
+    pub last_skew_ms: i64,
+    pub last_received_at: Timestamp,
+    /// Dataframes the estimate is based on
+    pub samples: u64,
+    /// Dataframes received while the offset exceeded the threshold
+    pub skewed: u64
+}
+
+/// Has to be called before the first request is served
+pub fn init_clocks(config: &ClockConfig) -> Result<(), Error> {
+    let clocks: Clocks = Clocks {
+        threshold_ms: i64::try_from(config.skew_threshold_ms).unwrap_or(i64::MAX),
+        action: config.skew_action,
+        devices: Mutex::new(HashMap::new())
+    };
+
+    CLOCKS.set(clocks).map_err(|_| Error::ConfigError("Clocks are already initialised".to_string()))
+}
+
+pub fn clocks() -> &'static Clocks {
+    CLOCKS.get().expect("init_clocks has to be called before serving requests")
+}
+
+impl Clocks {
+    /// Updates the estimated offset of the device with the skew of a dataframe that was sent right away, then applies
+    /// the configured action like [`Self::adjust`]
+    pub fn observe(&self, dataframe: &mut DataFrame) {
+        if !dataframe.time_stamp_supplied {
+            return;
+        }
+
+        let skew_ms: i64 = (dataframe.received_at - dataframe.time_stamp).num_milliseconds();
+        let mut devices = self.devices.lock().expect("Implement Logging | clocks poisoned");
+
+        let clock: &mut DeviceClock = devices.entry(dataframe.device_id).or_insert(DeviceClock {
+            device_id: dataframe.device_id,
+            offset_ms: skew_ms,
+            last_skew_ms: skew_ms,
+            last_received_at: dataframe.received_at,
+            samples: 0,
+            skewed: 0
+        });
+
+        // A jump beyond the threshold means the clock was reset, e.g. by a reboot, so the old estimate is useless
+        if (skew_ms - clock.offset_ms).abs() > self.threshold_ms {
+            clock.offset_ms = skew_ms;
+        } else {
+            clock.offset_ms += ((skew_ms - clock.offset_ms) as f64 * SMOOTHING).round() as i64;
+        }
+
+        clock.last_skew_ms = skew_ms;
+        clock.last_received_at = dataframe.received_at;
+        clock.samples += 1;
+
+        self.apply(clock, dataframe);
+    }
+
+    /// Flags or corrects the dataframe if the clock of its device is known to be off, without updating the estimate
+    pub fn adjust(&self, dataframe: &mut DataFrame) {
+        if !dataframe.time_stamp_supplied {
+            return;
+        }
+
+        let mut devices = self.devices.lock().expect("Implement Logging | clocks poisoned");
+
+        if let Some(clock) = devices.get_mut(&dataframe.device_id) {
+            self.apply(clock, dataframe);
+        }
+    }
+
+    fn apply(&self, clock: &mut DeviceClock, dataframe: &mut DataFrame) {
+        if clock.offset_ms.abs() <= self.threshold_ms {
+            return;
+        }
+
+        clock.skewed += 1;
+
+        match self.action {
+            SkewAction::None => {},
+            SkewAction::Flag => dataframe.skewed = true,
+            SkewAction::Correct => {
+                dataframe.skewed = true;
+
+                if let Some(corrected) = dataframe.time_stamp.checked_add_signed(TimeDelta::milliseconds(clock.offset_ms)) {
+                    dataframe.device_time_stamp = Some(dataframe.time_stamp);
+                    dataframe.time_stamp = corrected;
+                }
+            }
+        }
+    }
+
+    /// Every device a dataframe was received from since startup, ordered by ID
+    pub fn list(&self) -> Vec<DeviceClock> {
+        let mut clocks: Vec<DeviceClock> = self.devices.lock().expect("Implement Logging | clocks poisoned").values().cloned().collect();
+        clocks.sort_by_key(|clock| clock.device_id);
+        clocks
+    }
+}
@@ -1,79 +1,269 @@
-use postgres::{Client, Row};
+use postgres::{Client, GenericClient, Portal, Row, Transaction};
+use postgres::types::{Json, ToSql};
+use serde_json::{Map, Value};
 
 use crate::error::Error;
-use super::dataformat::{DataFrame, Query};
+use super::timestamp::Timestamp;
+use super::filter::Filter;
+use super::aggregation::{Aggregation, Bucket, FieldStatistics};
+use super::page::{Cursor, Page, PageRequest};
+use super::dataformat::DataFrame;
 
+use std::collections::{BTreeMap, HashSet};
 
-const CONDITION_TIME_SPAN_AND_DEVICE: &str = "FROM Dataframe WHERE device_id = $1 AND $2 <=time_stamp AND time_stamp<=$3";
-const CONDITION_TIME_OLDER_AND_DEVICE: &str = "FROM Dataframe WHERE device_id = $1 AND $2 < time_stamp OR $2 = time_stamp";
-const CONDITION_TIME_YOUNGER_AND_DEVICE: &str = "FROM Dataframe WHERE device_id = $1 AND $2 > time_stamp OR $2 = time_stamp";
-const CONDITION_TIME_NOW_AND_DEVICE: &str = "FROM Dataframe WHERE device_id = $1 AND $2 = time_stamp";
 
-const SELECT_STATEMENT: &str = "SELECT * ";
-const DELETE_STATEMENT: &str = "DELETE ";
+const SELECT_STATEMENT: &str = "SELECT * FROM Dataframe";
+const COUNT_STATEMENT: &str = "SELECT count(*) FROM Dataframe";
+const DELETE_STATEMENT: &str = "DELETE FROM Dataframe";
+/// The conditions of the filter follow, numbered after these two parameters
+const UPDATE_STATEMENT: &str = "UPDATE Dataframe SET fields = $1";
+const UPDATE_TIME_STAMP_CLAUSE: &str = ", time_stamp = $2";
+const INSERT_STATEMENT: &str = "INSERT INTO Dataframe (device_id, time_stamp, message_id, received_at, skewed, device_time_stamp, fields) VALUES ";
+/// A repeated message ID of the same device is skipped. The returned keys tell which rows were actually inserted.
+const INSERT_CONFLICT_CLAUSE: &str = " ON CONFLICT (device_id, message_id) DO NOTHING RETURNING device_id, message_id";
 
-pub fn get(query: &Query, client: &mut Client) -> Result<Vec<DataFrame>, Error> {
-    let result: Result<Vec<Row>, Error> = match query {
-        Query { device_id: Some(id), time_start: Some(start), time_end: Some(end), time_now: None } => client.query(&(SELECT_STATEMENT.to_string() + CONDITION_TIME_SPAN_AND_DEVICE), &[&id, &start, &end]).map_err(|err| Error::DatabaseQueryFailed(err.to_string())),
-        Query { device_id: Some(id), time_start: Some(start), time_end: None, time_now: None } => client.query(&(SELECT_STATEMENT.to_string() + CONDITION_TIME_OLDER_AND_DEVICE), &[&id, &start]).map_err(|err| Error::DatabaseQueryFailed(err.to_string())),
-        Query { device_id: Some(id), time_start: None, time_end: Some(end), time_now: None } => client.query(&(SELECT_STATEMENT.to_string() + CONDITION_TIME_YOUNGER_AND_DEVICE), &[&id, &end]).map_err(|err| Error::DatabaseQueryFailed(err.to_string())),
-        Query { device_id: Some(id), time_start: None, time_end: None, time_now: Some(now) } => client.query(&(SELECT_STATEMENT.to_string() + CONDITION_TIME_NOW_AND_DEVICE), &[&id, &now]).map_err(|err| Error::DatabaseQueryFailed(err.to_string())),
-        _ => Err(Error::DatabaseQueryNotSupported)
+const INSERT_PARAMS_PER_ROW: usize = 7;
+
+/// The client encodes the parameter count as a signed 16 bit integer
+pub const MAX_INSERT_ROWS: usize = i16::MAX as usize / INSERT_PARAMS_PER_ROW;
+
+pub fn get(filter: &Filter, client: &mut Client) -> Result<Vec<DataFrame>, Error> {
+    let mut statement: String = SELECT_STATEMENT.to_string();
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+
+    filter.write_sql(&mut statement, &mut params);
+
+    let result: Result<Vec<Row>, Error> = client.query(&statement, &params).map_err(|err| Error::DatabaseQueryFailed(err.to_string()));
+
+    Ok(result?.iter().map(dataframe).collect())
+}
+
+/// Fetches the page selected by a filter from [`Filter::for_page`]. One row more than the limit is read, to know
+/// whether another page follows.
+pub fn get_page(filter: &Filter, request: &PageRequest, client: &mut Client) -> Result<Page, Error> {
+    let limit: i64 = filter.limit.expect("Filter::for_page always sets a limit");
+    let fetch: i64 = limit + 1;
+
+    let mut statement: String = SELECT_STATEMENT.to_string();
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+
+    filter.write_clauses(&mut statement, &mut params);
+
+    params.push(&fetch);
+    statement.push_str(&format!(" LIMIT ${}", params.len()));
+
+    let mut rows: Vec<Row> = client.query(&statement, &params).map_err(|err| Error::DatabaseQueryFailed(err.to_string()))?;
+
+    let more: bool = rows.len() as i64 > limit;
+    rows.truncate(limit as usize);
+
+    // Pages in any other order can't be continued
+    let next: Option<Cursor> = match (more, filter.keyset()) {
+        (true, Some(_)) => rows.last().map(|row| Cursor { time_stamp: row.get("time_stamp"), id: row.get("id") }),
+        _ => None
     };
 
-    Ok(result?.iter().map(|row| {
-            let temp: i16 = row.get("temp");
-            let rpm: i32 = row.get("rpm");
-            let device_id: i32 = row.get("device_id");
-            let time_stamp: i32 = row.get("time_stamp");
+    let total: Option<i64> = if request.count { Some(count(filter, client)?) } else { None };
+
+    Ok(Page::new(rows.iter().map(dataframe).collect(), next, total, request))
+}
+
+/// Reads the dataframes selected by a filter chunk by chunk through a portal, so the result never has to fit into
+/// memory. The portal lives in a transaction, which is rolled back once the export is dropped, as nothing was changed.
+pub struct Export<'a> {
+    transaction: Transaction<'a>,
+    portal: Portal
+}
+
+impl<'a> Export<'a> {
+    pub fn start(filter: &Filter, client: &'a mut Client) -> Result<Self, Error> {
+        let mut statement: String = SELECT_STATEMENT.to_string();
+        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+
+        filter.write_sql(&mut statement, &mut params);
+
+        let mut transaction: Transaction = client.transaction().map_err(|err| Error::DatabaseQueryFailed(err.to_string()))?;
+        let portal: Portal = transaction.bind(&statement, &params).map_err(|err| Error::DatabaseQueryFailed(err.to_string()))?;
+
+        Ok(Self { transaction, portal })
+    }
+
+    /// Up to `rows` further dataframes, none once every one was read
+    pub fn next_chunk(&mut self, rows: i32) -> Result<Vec<DataFrame>, Error> {
+        let rows: Vec<Row> = self.transaction.query_portal(&self.portal, rows).map_err(|err| Error::DatabaseQueryFailed(err.to_string()))?;
+
+        Ok(rows.iter().map(dataframe).collect())
+    }
+
+    /// Closes the portal together with the transaction. Nothing was written, so it is rolled back whether the export
+    /// finished or not.
+    pub fn close(self) -> Result<(), Error> {
+        self.transaction.rollback().map_err(|err| Error::DatabaseQueryFailed(err.to_string()))
+    }
+}
+
+/// Statistics per device and bucket, ordered by both. Buckets without dataframes are left out.
+pub fn aggregate(aggregation: &Aggregation, client: &mut Client) -> Result<Vec<Bucket>, Error> {
+    let fractions: Vec<f64> = aggregation.percentiles.iter().map(|(_, fraction)| *fraction).collect();
+
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![&aggregation.bucket_seconds];
+
+    // Every parameter has to be used, its type can't be determined otherwise
+    if !fractions.is_empty() {
+        params.push(&fractions);
+    }
+
+    let mut statement: String = String::from("SELECT device_id, to_timestamp(floor(extract(epoch FROM time_stamp)::double precision / $1::double precision) * $1::double precision) AS bucket, count(*) AS dataframes");
+
+    for field in &aggregation.fields {
+        params.push(field);
+
+        // Other schemas may use the same name for something else than a number
+        let value: String = format!("(CASE WHEN jsonb_typeof(fields -> ${0}::text) = 'number' THEN (fields ->> ${0}::text)::double precision END)", params.len());
+
+        statement.push_str(&format!(", count({value}), min({value}), max({value}), avg({value})"));
+
+        if !fractions.is_empty() {
+            statement.push_str(&format!(", percentile_cont($2::double precision[]) WITHIN GROUP (ORDER BY {value})"));
+        }
+    }
+
+    statement.push_str(" FROM Dataframe");
+    aggregation.filter.write_condition(&mut statement, &mut params);
+    statement.push_str(" GROUP BY device_id, bucket ORDER BY device_id, bucket");
+
+    let rows: Vec<Row> = client.query(&statement, &params).map_err(|err| Error::DatabaseQueryFailed(err.to_string()))?;
+    let columns: usize = if fractions.is_empty() { 4 } else { 5 };
+
+    Ok(rows.iter().map(|row| {
+            let fields: BTreeMap<String, FieldStatistics> = aggregation.fields.iter().enumerate().map(|(index, field)| {
+                let column: usize = 3 + index * columns;
+
+                // NULL if the bucket has no number for the field
+                let percentiles: Option<Vec<Option<f64>>> = if fractions.is_empty() { None } else { row.get(column + 4) };
+                let percentiles: Vec<Option<f64>> = percentiles.unwrap_or_else(|| vec![None; fractions.len()]);
+
+                let statistics: FieldStatistics = FieldStatistics {
+                    count: row.get(column),
+                    min: row.get(column + 1),
+                    max: row.get(column + 2),
+                    mean: row.get(column + 3),
+                    percentiles: aggregation.percentiles.iter().map(|(label, _)| label.clone()).zip(percentiles).collect()
+                };
 
-            DataFrame { temp, rpm, device_id, time_stamp }
+                (field.clone(), statistics)
+            }).collect();
+
+            Bucket { device_id: row.get("device_id"), start: row.get("bucket"), count: row.get("dataframes"), fields }
         }).collect()
     )
 }
 
-pub fn delete(query: &Query, client: &mut Client) -> Result<(), Error> {
-    if let Err(err)  = match query {
-        Query { device_id: Some(id), time_start: Some(start), time_end: Some(end), time_now: None } => client.execute(&(DELETE_STATEMENT.to_string() + CONDITION_TIME_SPAN_AND_DEVICE), &[&id, &start, &end]).map_err(|err| Error::DatabaseDeletionError(err.to_string())),
-        Query { device_id: Some(id), time_start: Some(start), time_end: None, time_now: None } => client.execute(&(DELETE_STATEMENT.to_string() + CONDITION_TIME_OLDER_AND_DEVICE), &[&id, &start]).map_err(|err| Error::DatabaseDeletionError(err.to_string())),
-        Query { device_id: Some(id), time_start: None, time_end: Some(end), time_now: None } => client.execute(&(DELETE_STATEMENT.to_string() + CONDITION_TIME_YOUNGER_AND_DEVICE), &[&id, &end]).map_err(|err| Error::DatabaseDeletionError(err.to_string())),
-        Query { device_id: Some(id), time_start: None, time_end: None, time_now: Some(now) } => client.execute(&(DELETE_STATEMENT.to_string() + CONDITION_TIME_NOW_AND_DEVICE), &[&id, &now]).map_err(|err| Error::DatabaseDeletionError(err.to_string())),
-        _ => Err(Error::DatabaseDeletionError("Unsupported deletion operation".to_string()))
-    } {
-        Err(err)
-    } else {
-        Ok(())
+/// Number of dataframes matching the filter on any page
+pub fn count(filter: &Filter, client: &mut Client) -> Result<i64, Error> {
+    let mut statement: String = COUNT_STATEMENT.to_string();
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+
+    filter.write_condition(&mut statement, &mut params);
+
+    let row: Row = client.query_one(&statement, &params).map_err(|err| Error::DatabaseQueryFailed(err.to_string()))?;
+
+    Ok(row.get(0))
+}
+
+fn dataframe(row: &Row) -> DataFrame {
+    let device_id: i32 = row.get("device_id");
+    let time_stamp: Timestamp = row.get("time_stamp");
+    let message_id: Option<String> = row.get("message_id");
+    let received_at: Timestamp = row.get("received_at");
+    let skewed: bool = row.get("skewed");
+    let device_time_stamp: Option<Timestamp> = row.get("device_time_stamp");
+    let Json(fields): Json<Map<String, Value>> = row.get("fields");
+
+    DataFrame { device_id, time_stamp, time_stamp_supplied: false, message_id, received_at, skewed, device_time_stamp, fields }
+}
+
+/// Inserts all dataframes with a single multi-row INSERT. Either all rows are inserted or none. Returns for every
+/// dataframe whether it was stored, `false` means its message ID was already known for the device.
+pub fn insert<C: GenericClient>(dataframes: &[DataFrame], client: &mut C) -> Result<Vec<bool>, Error> {
+    if dataframes.len() > MAX_INSERT_ROWS {
+        return Err(Error::DatabaseInsertionError(format!("At most {MAX_INSERT_ROWS} rows can be inserted at once")));
+    }
+
+    let mut statement: String = INSERT_STATEMENT.to_string();
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::with_capacity(dataframes.len() * INSERT_PARAMS_PER_ROW);
+
+    // Encoded as JSONB, the wrappers have to outlive `params`
+    let fields: Vec<Json<&Map<String, Value>>> = dataframes.iter().map(|dataframe| Json(&dataframe.fields)).collect();
+
+    for (row, DataFrame { device_id, time_stamp, message_id, received_at, skewed, device_time_stamp, .. }) in dataframes.iter().enumerate() {
+        let placeholders: Vec<String> = (1..=INSERT_PARAMS_PER_ROW).map(|param| format!("${}", row * INSERT_PARAMS_PER_ROW + param)).collect();
+
+        if row > 0 {
+            statement.push(',');
+        }
+
+        statement.push_str(&format!("({})", placeholders.join(", ")));
+        params.extend_from_slice(&[device_id, time_stamp, message_id, received_at, skewed, device_time_stamp, &fields[row]]);
+    }
+
+    statement.push_str(INSERT_CONFLICT_CLAUSE);
+
+    let rows: Vec<Row> = client.query(&statement, &params).map_err(|err| Error::DatabaseInsertionError(err.to_string()))?;
+
+    let mut inserted_keys: HashSet<(i32, String)> = rows.iter()
+        .filter_map(|row| Some((row.get("device_id"), row.get::<_, Option<String>>("message_id")?)))
+        .collect();
+
+    // A key repeated within the batch is only inserted once, the first occurrence counts as the original
+    Ok(dataframes.iter()
+        .map(|dataframe| match &dataframe.message_id {
+            Some(message_id) => inserted_keys.remove(&(dataframe.device_id, message_id.clone())),
+            None => true
+        })
+        .collect())
+}
+
+/// Inserts any number of dataframes in one transaction, split into statements of at most [`MAX_INSERT_ROWS`] rows.
+/// Returns the same as [`insert`].
+pub fn insert_all(dataframes: &[DataFrame], client: &mut Client) -> Result<Vec<bool>, Error> {
+    let mut transaction: Transaction = client.transaction().map_err(|err| Error::DatabaseInsertionError(err.to_string()))?;
+    let mut inserted: Vec<bool> = Vec::with_capacity(dataframes.len());
+
+    for chunk in dataframes.chunks(MAX_INSERT_ROWS) {
+        inserted.extend(insert(chunk, &mut transaction)?);
     }
+
+    transaction.commit().map_err(|err| Error::DatabaseInsertionError(err.to_string()))?;
+
+    Ok(inserted)
 }
 
-pub fn update(query: &Query, dataframe: &DataFrame, client: &mut Client) -> Result<(), Error> {
-    // Destructure the dataframe to extract the values to be updated
-    let DataFrame { temp, rpm, time_stamp, .. } = dataframe;
-
-    // Match on the query to determine the condition
-    let result = match query {
-        Query { device_id: Some(id), time_start: Some(start), time_end: Some(end), time_now: None } => {
-            let update_query: &str = "UPDATE Dataframe SET temp = $4, rpm = $5, time_stamp = $6 WHERE device_id = $1 AND $2 <= time_stamp AND time_stamp <= $3";
-            client.execute(update_query, &[&id, &start, &end, &temp, &rpm, &time_stamp])
-        },
-        Query { device_id: Some(id), time_start: Some(start), time_end: None, time_now: None } => {
-            let update_query: &str = "UPDATE Dataframe SET temp = $3, rpm = $4, time_stamp = $5 WHERE device_id = $1 AND $2 < time_stamp OR $2 time_stamp = $2";
-            client.execute(update_query, &[&id, &start, &temp, &rpm, &time_stamp])
-        },
-        Query { device_id: Some(id), time_start: None, time_end: Some(end), time_now: None } => {
-            let update_query: &str = "UPDATE Dataframe SET temp = $3, rpm = $4, time_stamp = $5 WHERE device_id = $1 AND time_stamp < $2 OR time_stamp = $2";
-            client.execute(update_query, &[&id, &end, &temp, &rpm, &time_stamp])
-        },
-        Query { device_id: Some(id), time_start: None, time_end: None, time_now: Some(now) } => {
-            let update_query: &str = "UPDATE Dataframe SET temp = $3, rpm = $4, time_stamp = $5 WHERE device_id = $1 AND time_stamp = $2";
-            client.execute(update_query, &[&id, &now, &temp, &rpm, &time_stamp])
-        },
-        _ => return Err(Error::DatabaseUpdateError("Unsupported update operation".to_string())),
-    };
+/// Returns the number of deleted dataframes
+pub fn delete(filter: &Filter, client: &mut Client) -> Result<u64, Error> {
+    let mut statement: String = DELETE_STATEMENT.to_string();
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+
+    filter.write_sql(&mut statement, &mut params);
+
+    client.execute(&statement, &params).map_err(|err| Error::DatabaseDeletionError(err.to_string()))
+}
 
-    // Check the result and return appropriately
-    match result {
-        Ok(_) => Ok(()),
-        Err(err) => Err(Error::DatabaseUpdateError(err.to_string())),
+/// Replaces the fields of every matching dataframe, and their timestamp if the device sent one. Returns the number of
+/// updated dataframes.
+pub fn update(filter: &Filter, dataframe: &DataFrame, client: &mut Client) -> Result<u64, Error> {
+    let fields: Json<&Map<String, Value>> = Json(&dataframe.fields);
+
+    let mut statement: String = UPDATE_STATEMENT.to_string();
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![&fields];
+
+    // Without a timestamp of its own, the dataframe must not move the rows to the time of the request
+    if dataframe.time_stamp_supplied {
+        statement.push_str(UPDATE_TIME_STAMP_CLAUSE);
+        params.push(&dataframe.time_stamp);
     }
+
+    filter.write_sql(&mut statement, &mut params);
+
+    client.execute(&statement, &params).map_err(|err| Error::DatabaseUpdateError(err.to_string()))
 }
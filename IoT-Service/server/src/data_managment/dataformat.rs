@@ -1,43 +1,97 @@
-use serde::{Serialize, Deserialize};
+//! A dataframe consists of the fixed keys every device sends and its measurements. Which measurements a device sends
+//! is described by a schema at runtime, see [`super::schema`].
+//! Each DataFrame represents one querry
 
+use chrono::Utc;
+use serde::{Serialize, Deserialize, Deserializer};
+use serde_json::{Map, Value};
 
-/// This modul is for the user. This allows for a generic implementation of a dataframe while still maintaining the efficiency and safety granted by typed datatypes.
-/// Each DataFrame represents one querry
+use super::timestamp::{self, Timestamp};
 
-/// Define your own Dataframe as you may see fit. Make sure that you sent everytime a valid dataframe via JSON.
+/// Sent as a flat JSON object, e.g. `{"device_id": 1, "time_stamp": "2024-05-01T12:00:00Z", "temp": 21, "rpm": 900}`
 #[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(from = "ReceivedDataFrame")]
 pub struct DataFrame {
-    pub temp: i16, // Struct member name must be identical to the corresponding json key
-    pub rpm: i32,  // Struct member name must be identical to the corresponding json key
     pub device_id: i32,  // Struct member name must be identical to the corresponding json key
-    pub time_stamp: i32,  // Struct member name must be identical to the corresponding json key
+    /// Set to the time of arrival if the device doesn't send one
+    pub time_stamp: Timestamp,  // Struct member name must be identical to the corresponding json key
+    /// The device sent `time_stamp` itself. Unknown for dataframes read from the database.
+    #[serde(skip_serializing)]
+    pub time_stamp_supplied: bool,
+    /// Optional client chosen ID. A dataframe repeating the ID of one already stored for the same device is dropped,
+    /// so devices can safely retry. The `Idempotency-Key` header sets it as well.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// Set by the server, see [`super::clock`]
+    pub received_at: Timestamp,
+    /// The device clock was off by more than the configured threshold
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub skewed: bool,
+    /// Timestamp as sent by the device, if `time_stamp` was corrected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_time_stamp: Option<Timestamp>,
+    /// Every other key, checked against the schema of the device
+    #[serde(flatten)]
+    pub fields: Map<String, Value>
 }
 
-/// Define your own Dataframe as you may see fit. Make sure that you sent everytime a valid dataframe via JSON.
-#[derive(Debug, Deserialize, Default)]
-pub struct Query {
-    #[serde(default = "default_device_id")]
-    pub device_id: Option<i32>,
-    #[serde(default = "default_time_start")]
-    pub time_start: Option<i32>,
-    #[serde(default = "default_time_end")]
-    pub time_end: Option<i32>,
-    #[serde(default = "default_time_now")]
-    pub time_now: Option<i32>
+/// The keys of a [`DataFrame`] a device may send
+#[derive(Deserialize)]
+struct ReceivedDataFrame {
+    device_id: i32,
+    #[serde(default, deserialize_with = "timestamp::deserialize_option")]
+    time_stamp: Option<Timestamp>,
+    #[serde(default)]
+    message_id: Option<String>,
+    #[serde(flatten)]
+    fields: Map<String, Value>
 }
 
-fn default_device_id<T>() -> Option<T> {
-    None
-}
+impl From<ReceivedDataFrame> for DataFrame {
+    fn from(received: ReceivedDataFrame) -> Self {
+        let received_at: Timestamp = Utc::now();
 
-fn default_time_start<T>() -> Option<T> {
-    None
+        Self {
+            device_id: received.device_id,
+            time_stamp: received.time_stamp.unwrap_or(received_at),
+            time_stamp_supplied: received.time_stamp.is_some(),
+            message_id: received.message_id,
+            received_at,
+            skewed: false,
+            device_time_stamp: None,
+            fields: received.fields
+        }
+    }
 }
 
-fn default_time_end<T>() -> Option<T> {
-    None
+/// Longest accepted `message_id`
+pub const MAX_MESSAGE_ID_LENGTH: usize = 128;
+
+/// The query parameters selecting dataframes for GET, DELETE and PUT, see [`super::filter`]
+#[derive(Debug, Deserialize, Default)]
+pub struct Query {
+    /// Comma separated, e.g. `device_id=1,2,3`
+    #[serde(default, deserialize_with = "device_ids")]
+    pub device_id: Vec<i32>,
+    #[serde(default, deserialize_with = "timestamp::deserialize_option")]
+    pub time_start: Option<Timestamp>,
+    #[serde(default, deserialize_with = "timestamp::deserialize_option")]
+    pub time_end: Option<Timestamp>,
+    #[serde(default, deserialize_with = "timestamp::deserialize_option")]
+    pub time_now: Option<Timestamp>,
+    /// Only used by GET, see [`super::filter`]
+    pub filter: Option<String>,
+    pub order: Option<String>,
+    pub limit: Option<i64>,
+    /// Only used by GET, see [`super::page`]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub count: bool
 }
 
-fn default_time_now<T>() -> Option<T> {
-    None
+fn device_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<i32>, D::Error> {
+    String::deserialize(deserializer)?
+        .split(',')
+        .map(|device_id| device_id.trim().parse::<i32>().map_err(serde::de::Error::custom))
+        .collect()
 }
@@ -0,0 +1,834 @@
+//! Filters for GET requests, given as query parameters next to the plain `device_id`, `time_start`, `time_end` and
+//! `time_now`:
+//!
+//! - `device_id=1,2,3` selects several devices at once
+//! - `filter=temp > 40 and (rpm between 100 and 200 or device_id in (1, 2))` selects by any column or schema field
+//! - `order=-temp,time_stamp` sorts by the given keys, descending with a leading `-`
+//! - `limit=100` caps the number of dataframes returned, see [`super::page`] for the pages longer results are split in
+//! - `cursor=...` continues after the page the cursor was taken from
+//! - `count=true` adds the number of matching dataframes across all pages
+//!
+//! Conditions compare a key with `=`, `!=`, `<`, `<=`, `>`, `>=`, `between ... and ...`, `in (...)` or `is [not] null`
+//! and are combined with `and`, `or`, `not` and parentheses. Values are numbers, `true`, `false` or strings in single
+//! quotes. Timestamps are given as seconds since the unix epoch, as quoted milliseconds followed by `ms`, e.g.
+//! `'1714564800000ms'`, or as quoted RFC 3339, see [`super::timestamp`].
+//!
+//! Values and field names are never spliced into the SQL, they are always passed as parameters. DELETE and PUT use the
+//! same parameters, except for `order`, `limit`, `cursor` and `count`. The devices given by `device_id` are always ANDed with the rest, so
+//! no `filter` can reach the rows of another device.
+
+use postgres::types::{Json, ToSql};
+use serde_json::{Number, Value};
+
+use crate::error::Error;
+use super::dataformat::Query;
+use super::page::Cursor;
+use super::schema::{is_valid_name, Schemas};
+use super::timestamp::{self, Timestamp};
+
+
+/// Longest accepted `filter` parameter
+pub const MAX_FILTER_LENGTH: usize = 4096;
+
+/// Deepest nesting of parentheses and `not`
+const MAX_DEPTH: usize = 32;
+
+/// A GET request translated into the WHERE, ORDER BY and LIMIT clauses of a SELECT
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub condition: Option<Condition>,
+    /// Only rows after the cursor, in the direction of [`Self::keyset`]
+    pub cursor: Option<Cursor>,
+    pub order: Vec<(Column, Direction)>,
+    pub limit: Option<i64>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    DeviceId,
+    TimeStamp,
+    MessageId,
+    ReceivedAt,
+    Skewed,
+    DeviceTimeStamp,
+    /// Orders rows with the same timestamp for paging, can't be filtered by
+    Id,
+    /// Measurement stored in the JSONB `fields` column
+    Field(String)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Ascending,
+    Descending
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual
+}
+
+/// Value of a condition, already converted to the type of the column it is compared with
+#[derive(Debug)]
+pub enum Literal {
+    Integer(i32),
+    Timestamp(Timestamp),
+    Text(String),
+    Boolean(bool),
+    Json(Json<Value>)
+}
+
+#[derive(Debug)]
+pub enum Condition {
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+    Not(Box<Condition>),
+    Compare(Column, Operator, Literal),
+    Between(Column, Literal, Literal),
+    In(Column, Vec<Literal>),
+    /// `true` for `is null`, `false` for `is not null`
+    IsNull(Column, bool)
+}
+
+impl Filter {
+    /// Combines the parameters of the request. Field names are checked against `schemas`, so a typo is reported
+    /// instead of silently matching nothing.
+    pub fn from_query(query: &Query, schemas: &Schemas) -> Result<Self, Error> {
+        let mut conditions: Vec<Condition> = Vec::new();
+
+        match query.device_id.as_slice() {
+            [] => {},
+            [device_id] => conditions.push(Condition::Compare(Column::DeviceId, Operator::Equal, Literal::Integer(*device_id))),
+            device_ids => conditions.push(Condition::In(Column::DeviceId, device_ids.iter().map(|device_id| Literal::Integer(*device_id)).collect()))
+        }
+
+        if let Some(start) = query.time_start {
+            conditions.push(Condition::Compare(Column::TimeStamp, Operator::GreaterOrEqual, Literal::Timestamp(start)));
+        }
+
+        if let Some(end) = query.time_end {
+            conditions.push(Condition::Compare(Column::TimeStamp, Operator::LessOrEqual, Literal::Timestamp(end)));
+        }
+
+        if let Some(now) = query.time_now {
+            conditions.push(Condition::Compare(Column::TimeStamp, Operator::Equal, Literal::Timestamp(now)));
+        }
+
+        if let Some(filter) = &query.filter {
+            conditions.push(parse_condition(filter, schemas)?);
+        }
+
+        let order: Vec<(Column, Direction)> = match &query.order {
+            Some(order) => parse_order(order, schemas)?,
+            None => Vec::new()
+        };
+
+        if query.limit.is_some_and(|limit| limit < 1) {
+            return Err(Error::InvalidFilter("limit must be at least 1".to_string()));
+        }
+
+        let condition: Option<Condition> = match conditions.len() {
+            0 => None,
+            1 => conditions.pop(),
+            _ => Some(Condition::And(conditions))
+        };
+
+        Ok(Self { condition, cursor: None, order, limit: query.limit })
+    }
+
+    /// Like [`Self::from_query`], for one page of a GET. Without `order`, the rows are ordered by time, which is the
+    /// only order that can be paged through. The limit defaults to and is capped by `max_page_size`.
+    pub fn for_page(query: &Query, schemas: &Schemas, max_page_size: i64) -> Result<Self, Error> {
+        let mut filter: Self = Self::from_query(query, schemas)?;
+        filter.order_by_time();
+
+        if let Some(cursor) = &query.cursor {
+            if filter.keyset().is_none() {
+                return Err(Error::InvalidFilter("cursor only works with pages ordered by time_stamp".to_string()));
+            }
+
+            filter.cursor = Some(Cursor::parse(cursor)?);
+        }
+
+        filter.limit = Some(filter.limit.map_or(max_page_size, |limit| limit.min(max_page_size)));
+
+        Ok(filter)
+    }
+
+    /// Like [`Self::from_query`], for an export of every matching dataframe, see
+    /// [`crate::connector::export::EXPORT_ENDPOINT`]. Without `order`, the rows are ordered by time.
+    pub fn for_export(query: &Query, schemas: &Schemas) -> Result<Self, Error> {
+        if query.cursor.is_some() || query.count {
+            return Err(Error::InvalidFilter("cursor and count are only supported for GET".to_string()));
+        }
+
+        let mut filter: Self = Self::from_query(query, schemas)?;
+        filter.order_by_time();
+
+        Ok(filter)
+    }
+
+    /// Like [`Self::from_query`], for DELETE and PUT. These have to name the devices they change and at least one
+    /// further condition, so a missing parameter can't wipe the data of a whole device.
+    pub fn for_modification(query: &Query, schemas: &Schemas) -> Result<Self, Error> {
+        if query.device_id.is_empty() {
+            return Err(Error::InvalidFilter("device_id is required".to_string()));
+        }
+
+        if query.time_start.is_none() && query.time_end.is_none() && query.time_now.is_none() && query.filter.is_none() {
+            return Err(Error::InvalidFilter("Expected time_start, time_end, time_now or filter next to device_id".to_string()));
+        }
+
+        if query.order.is_some() || query.limit.is_some() || query.cursor.is_some() || query.count {
+            return Err(Error::InvalidFilter("order, limit, cursor and count are only supported for GET".to_string()));
+        }
+
+        Self::from_query(query, schemas)
+    }
+
+    /// Orders by time if no other order was given. Rows with the same timestamp are kept in the order they were stored.
+    fn order_by_time(&mut self) {
+        if self.order.is_empty() {
+            self.order.push((Column::TimeStamp, Direction::Ascending));
+        }
+
+        if let [(Column::TimeStamp, direction)] = self.order[..] {
+            self.order.push((Column::Id, direction));
+        }
+    }
+
+    /// The direction rows are paged in, if they are ordered by time only
+    pub fn keyset(&self) -> Option<Direction> {
+        match self.order.as_slice() {
+            [(Column::TimeStamp, direction), (Column::Id, _)] => Some(*direction),
+            _ => None
+        }
+    }
+
+    /// Appends the clauses to `statement` and their values to `params`
+    pub fn write_sql<'a>(&'a self, statement: &mut String, params: &mut Vec<&'a (dyn ToSql + Sync)>) {
+        self.write_clauses(statement, params);
+
+        if let Some(limit) = &self.limit {
+            params.push(limit);
+            statement.push_str(&format!(" LIMIT ${}", params.len()));
+        }
+    }
+
+    /// The WHERE clause without the cursor, which matches the rows of every page
+    pub fn write_condition<'a>(&'a self, statement: &mut String, params: &mut Vec<&'a (dyn ToSql + Sync)>) {
+        if let Some(condition) = &self.condition {
+            statement.push_str(" WHERE ");
+            condition.write_sql(statement, params);
+        }
+    }
+
+    /// Every clause except LIMIT
+    pub fn write_clauses<'a>(&'a self, statement: &mut String, params: &mut Vec<&'a (dyn ToSql + Sync)>) {
+        self.write_condition(statement, params);
+
+        if let Some(cursor) = &self.cursor {
+            statement.push_str(if self.condition.is_some() { " AND " } else { " WHERE " });
+
+            params.push(&cursor.time_stamp);
+            params.push(&cursor.id);
+
+            let operator: &str = if self.keyset() == Some(Direction::Descending) { "<" } else { ">" };
+            statement.push_str(&format!("((time_stamp, id) {operator} (${}, ${}))", params.len() - 1, params.len()));
+        }
+
+        for (index, (column, direction)) in self.order.iter().enumerate() {
+            statement.push_str(if index == 0 { " ORDER BY " } else { ", " });
+            column.write_sql(statement, params);
+
+            if *direction == Direction::Descending {
+                statement.push_str(" DESC");
+            }
+        }
+    }
+}
+
+impl Condition {
+    fn write_sql<'a>(&'a self, statement: &mut String, params: &mut Vec<&'a (dyn ToSql + Sync)>) {
+        match self {
+            Self::And(conditions) | Self::Or(conditions) => {
+                let separator: &str = if matches!(self, Self::And(_)) { " AND " } else { " OR " };
+
+                statement.push('(');
+                for (index, condition) in conditions.iter().enumerate() {
+                    if index > 0 {
+                        statement.push_str(separator);
+                    }
+                    condition.write_sql(statement, params);
+                }
+                statement.push(')');
+            },
+            Self::Not(condition) => {
+                statement.push_str("NOT ");
+                condition.write_sql(statement, params);
+            },
+            Self::Compare(column, operator, value) => {
+                statement.push('(');
+                column.write_sql(statement, params);
+                statement.push_str(operator.as_sql());
+                value.write_sql(statement, params);
+                statement.push(')');
+            },
+            Self::Between(column, low, high) => {
+                statement.push('(');
+                column.write_sql(statement, params);
+                statement.push_str(" BETWEEN ");
+                low.write_sql(statement, params);
+                statement.push_str(" AND ");
+                high.write_sql(statement, params);
+                statement.push(')');
+            },
+            Self::In(column, values) => {
+                statement.push('(');
+                column.write_sql(statement, params);
+                statement.push_str(" IN (");
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        statement.push_str(", ");
+                    }
+                    value.write_sql(statement, params);
+                }
+                statement.push_str("))");
+            },
+            Self::IsNull(column, is_null) => {
+                statement.push('(');
+                column.write_sql(statement, params);
+                statement.push_str(if *is_null { " IS NULL)" } else { " IS NOT NULL)" });
+            }
+        }
+    }
+}
+
+impl Column {
+    fn from_name(name: &str, schemas: &Schemas) -> Result<Self, Error> {
+        match name {
+            "device_id" => Ok(Self::DeviceId),
+            "time_stamp" => Ok(Self::TimeStamp),
+            "message_id" => Ok(Self::MessageId),
+            "received_at" => Ok(Self::ReceivedAt),
+            "skewed" => Ok(Self::Skewed),
+            "device_time_stamp" => Ok(Self::DeviceTimeStamp),
+            name if is_valid_name(name) && schemas.declares(name) => Ok(Self::Field(name.to_string())),
+            name => Err(Error::InvalidFilter(format!("Unknown field `{name}`")))
+        }
+    }
+
+    fn write_sql<'a>(&'a self, statement: &mut String, params: &mut Vec<&'a (dyn ToSql + Sync)>) {
+        match self {
+            Self::DeviceId => statement.push_str("device_id"),
+            Self::TimeStamp => statement.push_str("time_stamp"),
+            Self::MessageId => statement.push_str("message_id"),
+            Self::ReceivedAt => statement.push_str("received_at"),
+            Self::Skewed => statement.push_str("skewed"),
+            Self::DeviceTimeStamp => statement.push_str("device_time_stamp"),
+            Self::Id => statement.push_str("id"),
+            Self::Field(name) => {
+                params.push(name);
+                statement.push_str(&format!("(fields -> ${}::text)", params.len()));
+            }
+        }
+    }
+
+    /// Converts a value of the filter to the type of the column
+    fn literal(&self, token: &Token) -> Result<Literal, Error> {
+        let literal: Option<Literal> = match (self, token) {
+            (Self::DeviceId, Token::Number(number)) => number.as_i64().and_then(|number| i32::try_from(number).ok()).map(Literal::Integer),
+            (Self::TimeStamp | Self::ReceivedAt | Self::DeviceTimeStamp, Token::Number(number)) => match number.as_i64() {
+                Some(seconds) => Some(Literal::Timestamp(timestamp::from_seconds(seconds).map_err(Error::InvalidFilter)?)),
+                None => None
+            },
+            (Self::TimeStamp | Self::ReceivedAt | Self::DeviceTimeStamp, Token::Text(text)) => Some(Literal::Timestamp(timestamp::parse(text).map_err(Error::InvalidFilter)?)),
+            (Self::MessageId, Token::Text(text)) => Some(Literal::Text(text.clone())),
+            (Self::Skewed, Token::Boolean(boolean)) => Some(Literal::Boolean(*boolean)),
+            (Self::Field(_), Token::Number(number)) => Some(Literal::Json(Json(Value::Number(number.clone())))),
+            (Self::Field(_), Token::Text(text)) => Some(Literal::Json(Json(Value::String(text.clone())))),
+            (Self::Field(_), Token::Boolean(boolean)) => Some(Literal::Json(Json(Value::Bool(*boolean)))),
+            _ => None
+        };
+
+        literal.ok_or_else(|| Error::InvalidFilter(format!("Invalid value {token} for `{}`", self.name())))
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Self::DeviceId => "device_id",
+            Self::TimeStamp => "time_stamp",
+            Self::MessageId => "message_id",
+            Self::ReceivedAt => "received_at",
+            Self::Skewed => "skewed",
+            Self::DeviceTimeStamp => "device_time_stamp",
+            Self::Id => "id",
+            Self::Field(name) => name
+        }
+    }
+}
+
+impl Operator {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            Self::Equal => " = ",
+            Self::NotEqual => " <> ",
+            Self::Less => " < ",
+            Self::LessOrEqual => " <= ",
+            Self::Greater => " > ",
+            Self::GreaterOrEqual => " >= "
+        }
+    }
+}
+
+impl Literal {
+    fn write_sql<'a>(&'a self, statement: &mut String, params: &mut Vec<&'a (dyn ToSql + Sync)>) {
+        let (param, cast): (&(dyn ToSql + Sync), &str) = match self {
+            Self::Integer(integer) => (integer, ""),
+            Self::Timestamp(timestamp) => (timestamp, ""),
+            Self::Text(text) => (text, ""),
+            Self::Boolean(boolean) => (boolean, ""),
+            Self::Json(json) => (json, "::jsonb")
+        };
+
+        params.push(param);
+        statement.push_str(&format!("${}{cast}", params.len()));
+    }
+}
+
+/// `-temp,time_stamp` sorts by temp descending, then by time_stamp
+pub fn parse_order(order: &str, schemas: &Schemas) -> Result<Vec<(Column, Direction)>, Error> {
+    order.split(',')
+        .map(str::trim)
+        .map(|key| match key.strip_prefix('-') {
+            Some(key) => Ok((Column::from_name(key.trim(), schemas)?, Direction::Descending)),
+            None => Ok((Column::from_name(key, schemas)?, Direction::Ascending))
+        })
+        .collect()
+}
+
+pub fn parse_condition(filter: &str, schemas: &Schemas) -> Result<Condition, Error> {
+    if filter.len() > MAX_FILTER_LENGTH {
+        return Err(Error::InvalidFilter(format!("filter must not be longer than {MAX_FILTER_LENGTH} characters")));
+    }
+
+    let mut parser: Parser = Parser { tokens: tokenize(filter)?, position: 0, depth: 0, schemas };
+    let condition: Condition = parser.or()?;
+
+    match parser.peek() {
+        None => Ok(condition),
+        Some(token) => Err(Error::InvalidFilter(format!("Unexpected {token}")))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// Field names and keywords, keywords are lowercased
+    Word(String),
+    Number(Number),
+    Text(String),
+    Boolean(bool),
+    Operator(Operator),
+    Open,
+    Close,
+    Comma
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Word(word) => write!(f, "`{word}`"),
+            Self::Number(number) => write!(f, "`{number}`"),
+            Self::Text(text) => write!(f, "'{text}'"),
+            Self::Boolean(boolean) => write!(f, "`{boolean}`"),
+            Self::Operator(operator) => write!(f, "`{}`", operator.as_sql().trim()),
+            Self::Open => write!(f, "`(`"),
+            Self::Close => write!(f, "`)`"),
+            Self::Comma => write!(f, "`,`")
+        }
+    }
+}
+
+fn tokenize(filter: &str) -> Result<Vec<Token>, Error> {
+    let chars: Vec<char> = filter.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut position: usize = 0;
+
+    while let Some(&char) = chars.get(position) {
+        let next: Option<char> = chars.get(position + 1).copied();
+
+        let token: Token = match char {
+            _ if char.is_whitespace() => {
+                position += 1;
+                continue;
+            },
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            '=' => Token::Operator(Operator::Equal),
+            '!' if next == Some('=') => Token::Operator(Operator::NotEqual),
+            '<' if next == Some('>') => Token::Operator(Operator::NotEqual),
+            '<' if next == Some('=') => Token::Operator(Operator::LessOrEqual),
+            '<' => Token::Operator(Operator::Less),
+            '>' if next == Some('=') => Token::Operator(Operator::GreaterOrEqual),
+            '>' => Token::Operator(Operator::Greater),
+            '\'' => {
+                // A quote inside the string is written twice, like in SQL
+                let mut text: String = String::new();
+                position += 1;
+
+                loop {
+                    match (chars.get(position), chars.get(position + 1)) {
+                        (Some('\''), Some('\'')) => {
+                            text.push('\'');
+                            position += 2;
+                        },
+                        (Some('\''), _) => break,
+                        (Some(char), _) => {
+                            text.push(*char);
+                            position += 1;
+                        },
+                        (None, _) => return Err(Error::InvalidFilter("Unterminated string".to_string()))
+                    }
+                }
+
+                Token::Text(text)
+            },
+            _ if char.is_ascii_digit() || (char == '-' && next.is_some_and(|next| next.is_ascii_digit())) => {
+                let end: usize = (position + 1..chars.len())
+                    .find(|end| !(chars[*end].is_ascii_alphanumeric() || matches!(chars[*end], '.' | '+' | '-')))
+                    .unwrap_or(chars.len());
+                let number: String = chars[position..end].iter().collect();
+
+                tokens.push(Token::Number(serde_json::from_str(&number).map_err(|_| Error::InvalidFilter(format!("Invalid number `{number}`")))?));
+                position = end;
+                continue;
+            },
+            _ if char.is_ascii_alphabetic() || char == '_' => {
+                let end: usize = (position..chars.len()).find(|end| !(chars[*end].is_ascii_alphanumeric() || chars[*end] == '_')).unwrap_or(chars.len());
+                let word: String = chars[position..end].iter().collect();
+
+                tokens.push(match word.to_ascii_lowercase().as_str() {
+                    "true" => Token::Boolean(true),
+                    "false" => Token::Boolean(false),
+                    keyword @ ("and" | "or" | "not" | "between" | "in" | "is" | "null") => Token::Word(keyword.to_string()),
+                    _ => Token::Word(word)
+                });
+                position = end;
+                continue;
+            },
+            _ => return Err(Error::InvalidFilter(format!("Unexpected character `{char}`")))
+        };
+
+        position += match token {
+            Token::Operator(Operator::NotEqual | Operator::LessOrEqual | Operator::GreaterOrEqual) => 2,
+            _ => 1
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens, `not` binds tighter than `and`, which binds tighter than `or`
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+    schemas: &'a Schemas
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, Error> {
+        let token: Token = self.tokens.get(self.position).cloned().ok_or_else(|| Error::InvalidFilter("Unexpected end of filter".to_string()))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    /// Consumes the keyword if it comes next
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found: bool = matches!(self.peek(), Some(Token::Word(word)) if word == keyword);
+        self.position += found as usize;
+        found
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), Error> {
+        match self.next() {
+            Ok(token) if token == expected => Ok(()),
+            Ok(token) => Err(Error::InvalidFilter(format!("Expected {expected}, found {token}"))),
+            Err(_) => Err(Error::InvalidFilter(format!("Expected {expected} at the end of filter")))
+        }
+    }
+
+    fn or(&mut self) -> Result<Condition, Error> {
+        let mut conditions: Vec<Condition> = vec![self.and()?];
+
+        while self.keyword("or") {
+            conditions.push(self.and()?);
+        }
+
+        Ok(if conditions.len() == 1 { conditions.remove(0) } else { Condition::Or(conditions) })
+    }
+
+    fn and(&mut self) -> Result<Condition, Error> {
+        let mut conditions: Vec<Condition> = vec![self.unary()?];
+
+        while self.keyword("and") {
+            conditions.push(self.unary()?);
+        }
+
+        Ok(if conditions.len() == 1 { conditions.remove(0) } else { Condition::And(conditions) })
+    }
+
+    fn unary(&mut self) -> Result<Condition, Error> {
+        self.depth += 1;
+
+        if self.depth > MAX_DEPTH {
+            return Err(Error::InvalidFilter(format!("filter must not be nested deeper than {MAX_DEPTH} levels")));
+        }
+
+        let condition: Condition = if self.keyword("not") {
+            Condition::Not(Box::new(self.unary()?))
+        } else if self.peek() == Some(&Token::Open) {
+            self.position += 1;
+            let condition: Condition = self.or()?;
+            self.expect(Token::Close)?;
+            condition
+        } else {
+            self.comparison()?
+        };
+
+        self.depth -= 1;
+
+        Ok(condition)
+    }
+
+    fn comparison(&mut self) -> Result<Condition, Error> {
+        let column: Column = match self.next()? {
+            Token::Word(name) => Column::from_name(&name, self.schemas)?,
+            token => return Err(Error::InvalidFilter(format!("Expected a field, found {token}")))
+        };
+
+        match self.next()? {
+            Token::Operator(operator) => {
+                let value: Token = self.next()?;
+                Ok(Condition::Compare(column.clone(), operator, column.literal(&value)?))
+            },
+            Token::Word(word) if word == "between" => {
+                let low: Literal = column.literal(&self.next()?)?;
+                self.expect(Token::Word("and".to_string()))?;
+                let high: Literal = column.literal(&self.next()?)?;
+
+                Ok(Condition::Between(column, low, high))
+            },
+            Token::Word(word) if word == "in" => {
+                self.expect(Token::Open)?;
+                let mut values: Vec<Literal> = vec![column.literal(&self.next()?)?];
+
+                while self.peek() == Some(&Token::Comma) {
+                    self.position += 1;
+                    values.push(column.literal(&self.next()?)?);
+                }
+
+                self.expect(Token::Close)?;
+
+                Ok(Condition::In(column, values))
+            },
+            Token::Word(word) if word == "is" => {
+                let is_null: bool = !self.keyword("not");
+                self.expect(Token::Word("null".to_string()))?;
+
+                Ok(Condition::IsNull(column, is_null))
+            },
+            token => Err(Error::InvalidFilter(format!("Expected a comparison after `{}`, found {token}", column.name())))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+
+
+    /// The SQL of a filter and its parameters as debug output
+    fn sql(filter: &str) -> (String, String) {
+        let condition: Condition = parse_condition(filter, &Schemas::default()).unwrap();
+
+        let mut statement: String = String::new();
+        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+        condition.write_sql(&mut statement, &mut params);
+
+        (statement, format!("{params:?}"))
+    }
+
+    fn reason(result: Result<impl std::fmt::Debug, Error>) -> String {
+        match result {
+            Err(Error::InvalidFilter(reason)) => reason,
+            other => panic!("expected an invalid filter, got {other:?}")
+        }
+    }
+
+    fn invalid(filter: &str) -> String {
+        reason(parse_condition(filter, &Schemas::default()))
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            sql("temp = 1 or temp = 2 and rpm = 3").0,
+            "(((fields -> $1::text) = $2::jsonb) OR (((fields -> $3::text) = $4::jsonb) AND ((fields -> $5::text) = $6::jsonb)))"
+        );
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        assert_eq!(
+            sql("(temp = 1 or temp = 2) and rpm = 3").0,
+            "((((fields -> $1::text) = $2::jsonb) OR ((fields -> $3::text) = $4::jsonb)) AND ((fields -> $5::text) = $6::jsonb))"
+        );
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        assert_eq!(sql("not temp = 1 and rpm = 2").0, "(NOT ((fields -> $1::text) = $2::jsonb) AND ((fields -> $3::text) = $4::jsonb))");
+        assert_eq!(sql("not (temp = 1 and rpm = 2)").0, "NOT (((fields -> $1::text) = $2::jsonb) AND ((fields -> $3::text) = $4::jsonb))");
+    }
+
+    #[test]
+    fn keywords_ignore_case() {
+        assert_eq!(sql("NOT temp = 1 AND skewed IS NULL").0, sql("not temp = 1 and skewed is null").0);
+    }
+
+    #[test]
+    fn doubled_quotes_escape_a_quote() {
+        assert_eq!(sql("message_id = 'it''s'"), ("(message_id = $1)".to_string(), r#"["it's"]"#.to_string()));
+        assert_eq!(sql("message_id = ''''"), ("(message_id = $1)".to_string(), r#"["'"]"#.to_string()));
+    }
+
+    #[test]
+    fn values_never_reach_the_statement() {
+        let (statement, params) = sql(r#"message_id = '1); DROP TABLE Dataframe; --' or temp = 'x\ "y'"#);
+
+        assert_eq!(statement, "((message_id = $1) OR ((fields -> $2::text) = $3::jsonb))");
+        assert_eq!(params, r#"["1); DROP TABLE Dataframe; --", "temp", Json(String("x\\ \"y"))]"#);
+    }
+
+    #[test]
+    fn unterminated_strings_are_rejected() {
+        assert_eq!(invalid("message_id = 'open"), "Unterminated string");
+        assert_eq!(invalid("message_id = 'it''"), "Unterminated string");
+    }
+
+    #[test]
+    fn values_are_converted_to_the_column_type() {
+        assert_eq!(
+            sql("device_id != 3 and time_stamp >= 20 and skewed = true"),
+            ("((device_id <> $1) AND (time_stamp >= $2) AND (skewed = $3))".to_string(), "[3, 1970-01-01T00:00:20Z, true]".to_string())
+        );
+        assert_eq!(sql("time_stamp < '1970-01-01T01:00:20+01:00'").1, "[1970-01-01T00:00:20Z]");
+        assert_eq!(sql("time_stamp < '20250ms'").1, "[1970-01-01T00:00:20.250Z]");
+
+        assert_eq!(invalid("message_id = 1"), "Invalid value `1` for `message_id`");
+        assert_eq!(invalid("skewed = 1"), "Invalid value `1` for `skewed`");
+        assert_eq!(invalid("device_id = 99999999999"), "Invalid value `99999999999` for `device_id`");
+        assert!(invalid("time_stamp = 'yesterday'").starts_with("Invalid timestamp `yesterday`"));
+        assert!(invalid("time_stamp = 1714564800000").starts_with("Timestamp `1714564800000` is not between the years"));
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert_eq!(invalid("wind = 1"), "Unknown field `wind`");
+        assert_eq!(invalid("TEMP = 1"), "Unknown field `TEMP`");
+        assert_eq!(invalid("fields = 1"), "Unknown field `fields`");
+        assert_eq!(invalid("id = 1"), "Unknown field `id`");
+        assert_eq!(reason(parse_order("-wind", &Schemas::default())), "Unknown field `wind`");
+    }
+
+    #[test]
+    fn unknown_operators_are_rejected() {
+        assert_eq!(invalid("temp ~ 1"), "Unexpected character `~`");
+        assert_eq!(invalid("temp like 1"), "Expected a comparison after `temp`, found `like`");
+        assert_eq!(invalid("temp == 1"), "Invalid value `=` for `temp`");
+        assert_eq!(invalid("temp = 1 xor rpm = 1"), "Unexpected `xor`");
+    }
+
+    #[test]
+    fn incomplete_filters_are_rejected() {
+        assert_eq!(invalid("temp ="), "Unexpected end of filter");
+        assert_eq!(invalid("(temp = 1"), "Expected `)` at the end of filter");
+        assert_eq!(invalid("temp = 1)"), "Unexpected `)`");
+        assert_eq!(invalid("rpm between 1 or 2"), "Expected `and`, found `or`");
+        assert_eq!(invalid("skewed is true"), "Expected `null`, found `true`");
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth: usize| format!("{}temp = 1{}", "(".repeat(depth), ")".repeat(depth));
+        let too_deep: String = format!("filter must not be nested deeper than {MAX_DEPTH} levels");
+
+        assert!(parse_condition(&nested(MAX_DEPTH - 1), &Schemas::default()).is_ok());
+        assert_eq!(invalid(&nested(MAX_DEPTH)), too_deep);
+        assert_eq!(invalid(&format!("{}temp = 1", "not ".repeat(MAX_DEPTH))), too_deep);
+    }
+
+    #[test]
+    fn length_is_limited() {
+        let filter: String = format!("message_id = '{}'", "x".repeat(MAX_FILTER_LENGTH));
+        assert_eq!(invalid(&filter), format!("filter must not be longer than {MAX_FILTER_LENGTH} characters"));
+    }
+
+    #[test]
+    fn device_lists() {
+        let write = |device_id: Vec<i32>| {
+            let filter: Filter = Filter::from_query(&Query { device_id, ..Query::default() }, &Schemas::default()).unwrap();
+
+            let mut statement: String = String::new();
+            filter.write_sql(&mut statement, &mut Vec::new());
+            statement
+        };
+
+        assert_eq!(write(vec![]), "");
+        assert_eq!(write(vec![1]), " WHERE (device_id = $1)");
+        assert_eq!(write(vec![1, 2]), " WHERE (device_id IN ($1, $2))");
+
+        assert_eq!(invalid("device_id in ()"), "Invalid value `)` for `device_id`");
+        assert_eq!(invalid("device_id in (1,)"), "Invalid value `)` for `device_id`");
+        assert_eq!(reason(Filter::for_modification(&Query { filter: Some("temp = 1".to_string()), ..Query::default() }, &Schemas::default())), "device_id is required");
+    }
+
+    #[test]
+    fn placeholders_continue_after_earlier_params() {
+        let query: Query = Query {
+            device_id: vec![1, 2],
+            filter: Some("temp in (1, 2) or rpm between 3 and 4".to_string()),
+            cursor: Some(Cursor { time_stamp: DateTime::from_timestamp(20, 0).unwrap(), id: 7 }.to_string()),
+            ..Query::default()
+        };
+        let filter: Filter = Filter::for_page(&query, &Schemas::default(), 100).unwrap();
+
+        // Like UPDATE, which binds its new values first
+        let earlier: i32 = 0;
+        let mut statement: String = String::new();
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![&earlier];
+        filter.write_sql(&mut statement, &mut params);
+
+        assert_eq!(
+            statement,
+            " WHERE ((device_id IN ($2, $3)) AND (((fields -> $4::text) IN ($5::jsonb, $6::jsonb)) OR ((fields -> $7::text) BETWEEN $8::jsonb AND $9::jsonb))) \
+             AND ((time_stamp, id) > ($10, $11)) ORDER BY time_stamp, id LIMIT $12"
+        );
+        assert_eq!(params.len(), 12);
+    }
+}
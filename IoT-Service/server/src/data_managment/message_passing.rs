@@ -1,17 +1,39 @@
 use hyper::StatusCode;
+use hyper::body::Bytes;
+use tokio::sync::{mpsc, SemaphorePermit};
 use tokio::sync::oneshot::Sender;
 
-use super::dataformat::{DataFrame, Query};
+use crate::error::Error;
+use super::filter::Filter;
+use super::aggregation::Aggregation;
+use super::page::PageRequest;
+use super::dataformat::DataFrame;
 
 /// This will be send over channels to the working thread to be processed
 /// The enum variant wrapping the inner data defines the method, what should be done with the data
 /// Message passing is necessary, since you can't have blocking fuctions (database connection) on async channels
 pub enum Message {
-    Post(DataFrame),
+    /// The sender is `None` if the client doesn't wait for the insert, see `Prefer: respond-async`
+    Post(DataFrame, Option<Sender<Box<dyn Response>>>),
     // Sender is used for a bi directional channel. Take a look at get_request in middleware to get a better idea
-    Get(Query, Sender<Box<dyn Response>>),
-    Delete(Query, Sender<Box<dyn Response>>),
-    Put(Query, DataFrame, Sender<Box<dyn Response>>)
+    Get(Filter, PageRequest, Sender<Box<dyn Response>>),
+    /// Replies once the query started, then streams the dataframes as NDJSON chunks, see [`ExportChunk`]. The permit
+    /// is one of the export slots, released once the worker is done.
+    Export(Filter, Sender<Box<dyn Response>>, mpsc::Sender<ExportChunk>, SemaphorePermit<'static>),
+    Aggregate(Aggregation, Sender<Box<dyn Response>>),
+    Delete(Filter, Sender<Box<dyn Response>>),
+    Put(Filter, DataFrame, Sender<Box<dyn Response>>),
+    /// Inserted in a single transaction, either all dataframes are stored or none
+    Bulk(Vec<DataFrame>, Sender<Box<dyn Response>>)
+}
+
+/// Part of an export. A stream that closes without [`ExportChunk::End`] was cut short.
+#[derive(Debug)]
+pub enum ExportChunk {
+    Data(Bytes),
+    /// Every dataframe was sent
+    End,
+    Failed(Error)
 }
 
 pub struct ResponseMessage<T> {
@@ -0,0 +1,93 @@
+//! Forward only schema migrations. The SQL is embedded in the binary and applied in order on startup, every migration
+//! in its own transaction. The applied versions are tracked in the `schema_version` table.
+//!
+//! To change the schema, add a new file to `migrations/` and append it to [`MIGRATIONS`]. Never edit a migration that
+//! has already been released.
+
+use postgres::{Client, Row, Transaction};
+
+use crate::error::Error;
+
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "create_dataframe", sql: include_str!("migrations/0001_create_dataframe.sql") },
+    Migration { version: 2, name: "index_device_time_stamp", sql: include_str!("migrations/0002_index_device_time_stamp.sql") },
+    Migration { version: 3, name: "add_message_id", sql: include_str!("migrations/0003_add_message_id.sql") },
+    Migration { version: 4, name: "time_stamp_timestamptz", sql: include_str!("migrations/0004_time_stamp_timestamptz.sql") },
+    Migration { version: 5, name: "add_received_at", sql: include_str!("migrations/0005_add_received_at.sql") },
+    Migration { version: 6, name: "fields_jsonb", sql: include_str!("migrations/0006_fields_jsonb.sql") },
+    Migration { version: 7, name: "add_id", sql: include_str!("migrations/0007_add_id.sql") }
+];
+
+/// Held while migrating, so several servers starting at once don't apply the same migration twice
+const MIGRATION_LOCK: i64 = 0x696f_745f_6d69_6772;
+
+const CREATE_VERSION_TABLE: &str = "CREATE TABLE IF NOT EXISTS schema_version (
+    version         int PRIMARY KEY,
+    name            text NOT NULL,
+    applied_at      timestamptz NOT NULL DEFAULT now()
+)";
+
+/// Highest applied version, 0 for a fresh database. Doesn't modify the database, so it can be used for dry runs.
+pub fn current_version(client: &mut Client) -> Result<i32, Error> {
+    let exists: Row = client.query_one("SELECT to_regclass('schema_version') IS NOT NULL AS exists", &[])
+        .map_err(|err| Error::DatabaseMigrationError(err.to_string()))?;
+
+    if !exists.get::<_, bool>("exists") {
+        return Ok(0);
+    }
+
+    let row: Row = client.query_one("SELECT COALESCE(MAX(version), 0) AS version FROM schema_version", &[])
+        .map_err(|err| Error::DatabaseMigrationError(err.to_string()))?;
+
+    Ok(row.get("version"))
+}
+
+/// Migrations that haven't been applied yet. Fails if the database was migrated by a newer version of the server.
+pub fn pending(client: &mut Client) -> Result<Vec<&'static Migration>, Error> {
+    let version: i32 = current_version(client)?;
+    let latest: i32 = MIGRATIONS.last().map_or(0, |migration| migration.version);
+
+    if version > latest {
+        return Err(Error::DatabaseMigrationError(format!("Database schema is at version {version}, but this server only knows up to version {latest}")));
+    }
+
+    Ok(MIGRATIONS.iter().filter(|migration| migration.version > version).collect())
+}
+
+/// Applies all pending migrations and returns them
+pub fn migrate(client: &mut Client) -> Result<Vec<&'static Migration>, Error> {
+    client.execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK]).map_err(|err| Error::DatabaseMigrationError(err.to_string()))?;
+
+    let result: Result<Vec<&'static Migration>, Error> = apply_pending(client);
+
+    client.execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK]).map_err(|err| Error::DatabaseMigrationError(err.to_string()))?;
+
+    result
+}
+
+fn apply_pending(client: &mut Client) -> Result<Vec<&'static Migration>, Error> {
+    client.batch_execute(CREATE_VERSION_TABLE).map_err(|err| Error::DatabaseMigrationError(err.to_string()))?;
+
+    // Checked again after taking the lock, another server may have migrated in the meantime
+    let pending: Vec<&'static Migration> = pending(client)?;
+
+    for migration in &pending {
+        let mut transaction: Transaction = client.transaction().map_err(|err| Error::DatabaseMigrationError(err.to_string()))?;
+
+        transaction.batch_execute(migration.sql)
+            .and_then(|()| transaction.execute("INSERT INTO schema_version (version, name) VALUES ($1, $2)", &[&migration.version, &migration.name]))
+            .and_then(|_| transaction.commit())
+            .map_err(|err| Error::DatabaseMigrationError(format!("Migration {:04} {} failed: {err}", migration.version, migration.name)))?;
+
+        println!("Applied migration {:04} {}", migration.version, migration.name);
+    }
+
+    Ok(pending)
+}
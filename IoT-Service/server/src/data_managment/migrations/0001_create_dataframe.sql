@@ -0,0 +1,7 @@
+-- Databases set up before migrations existed already have this table
+CREATE TABLE IF NOT EXISTS Dataframe (
+    temp            smallint,
+    rpm             int,
+    device_id       int,
+    time_stamp      int
+);
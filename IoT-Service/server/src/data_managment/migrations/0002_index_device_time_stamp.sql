@@ -0,0 +1,2 @@
+-- Every query filters by device and time
+CREATE INDEX IF NOT EXISTS dataframe_device_id_time_stamp ON Dataframe (device_id, time_stamp);
@@ -0,0 +1,3 @@
+-- Client supplied IDs for idempotent ingestion, unique per device. Rows without an ID are never considered duplicates.
+ALTER TABLE Dataframe ADD COLUMN IF NOT EXISTS message_id text;
+CREATE UNIQUE INDEX IF NOT EXISTS dataframe_device_id_message_id ON Dataframe (device_id, message_id);
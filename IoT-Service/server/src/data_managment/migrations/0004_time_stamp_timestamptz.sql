@@ -0,0 +1,2 @@
+-- time_stamp used to hold seconds since the unix epoch
+ALTER TABLE Dataframe ALTER COLUMN time_stamp TYPE timestamptz USING to_timestamp(time_stamp);
@@ -0,0 +1,8 @@
+-- Rows stored before never recorded their arrival, the device time is the best guess
+ALTER TABLE Dataframe ADD COLUMN received_at timestamptz;
+UPDATE Dataframe SET received_at = time_stamp;
+ALTER TABLE Dataframe ALTER COLUMN received_at SET NOT NULL, ALTER COLUMN received_at SET DEFAULT now();
+
+ALTER TABLE Dataframe ADD COLUMN skewed boolean NOT NULL DEFAULT false;
+-- Original timestamp of a corrected dataframe
+ALTER TABLE Dataframe ADD COLUMN device_time_stamp timestamptz;
@@ -0,0 +1,5 @@
+-- Measurements are described by schemas at runtime, temp and rpm become fields of the default schema
+ALTER TABLE Dataframe ADD COLUMN fields jsonb NOT NULL DEFAULT '{}';
+UPDATE Dataframe SET fields = jsonb_strip_nulls(jsonb_build_object('temp', temp, 'rpm', rpm));
+ALTER TABLE Dataframe ALTER COLUMN fields DROP DEFAULT;
+ALTER TABLE Dataframe DROP COLUMN temp, DROP COLUMN rpm;
@@ -0,0 +1,6 @@
+-- Tie-breaker for paging by time_stamp, existing rows are numbered in no particular order
+ALTER TABLE Dataframe ADD COLUMN id bigserial PRIMARY KEY;
+-- Pages are read in (time_stamp, id) order, for a set of devices or across all of them
+DROP INDEX IF EXISTS dataframe_device_id_time_stamp;
+CREATE INDEX dataframe_device_id_time_stamp_id ON Dataframe (device_id, time_stamp, id);
+CREATE INDEX dataframe_time_stamp_id ON Dataframe (time_stamp, id);
@@ -1,3 +1,11 @@
+pub mod aggregation;
+pub mod clock;
 pub mod dataformat;
+pub mod filter;
 pub mod message_passing;
 pub mod database;
+pub mod migrations;
+pub mod page;
+pub mod schema;
+pub mod timestamp;
+pub mod worker;
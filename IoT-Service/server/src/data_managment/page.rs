@@ -0,0 +1,129 @@
+//! GET responses are split into pages of at most `query.max_page_size` dataframes, so a long history never has to be
+//! held in memory at once. Pages are ordered by `time_stamp`, rows with the same timestamp in the order they were
+//! stored. Instead of an offset, which gets slower with every page, the `next` link of a page carries a cursor naming
+//! the last row returned, and the following page continues right after it.
+//!
+//! A custom `order` by any other key returns the first page only, without `next` link.
+
+use base64::prelude::*;
+use chrono::DateTime;
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::config::QueryConfig;
+use super::dataformat::DataFrame;
+use super::timestamp::Timestamp;
+
+use std::fmt;
+use std::sync::OnceLock;
+
+
+static MAX_PAGE_SIZE: OnceLock<i64> = OnceLock::new();
+
+/// Position of the last row of a page, passed on as `cursor` parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub time_stamp: Timestamp,
+    pub id: i64
+}
+
+/// What a GET request asks for besides the filter
+#[derive(Debug)]
+pub struct PageRequest {
+    /// Count the matching dataframes across all pages
+    pub count: bool,
+    /// Link to the next page up to the value of its `cursor` parameter
+    pub link: String
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page {
+    pub response: Vec<DataFrame>,
+    /// Missing on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<i64>
+}
+
+/// Has to be called before the first request is served
+pub fn init_pages(config: &QueryConfig) -> Result<(), Error> {
+    MAX_PAGE_SIZE.set(config.max_page_size).map_err(|_| Error::ConfigError("Page size is already initialised".to_string()))
+}
+
+pub fn max_page_size() -> i64 {
+    *MAX_PAGE_SIZE.get().expect("init_pages has to be called before serving requests")
+}
+
+impl Cursor {
+    pub fn parse(cursor: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidFilter(format!("Invalid cursor `{cursor}`, use the next link of the previous page"));
+
+        let decoded: Vec<u8> = BASE64_URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+        let decoded: String = String::from_utf8(decoded).map_err(|_| invalid())?;
+
+        let (micros, id) = decoded.split_once(':').ok_or_else(invalid)?;
+        let micros: i64 = micros.parse().map_err(|_| invalid())?;
+
+        Ok(Self {
+            time_stamp: DateTime::from_timestamp_micros(micros).ok_or_else(invalid)?,
+            id: id.parse().map_err(|_| invalid())?
+        })
+    }
+}
+
+/// Opaque to clients, they only pass on what they got
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", BASE64_URL_SAFE_NO_PAD.encode(format!("{}:{}", self.time_stamp.timestamp_micros(), self.id)))
+    }
+}
+
+impl Page {
+    pub fn new(response: Vec<DataFrame>, next: Option<Cursor>, total: Option<i64>, request: &PageRequest) -> Self {
+        Self { response, next: next.map(|cursor| format!("{}{cursor}", request.link)), total }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn cursor() -> Cursor {
+        Cursor { time_stamp: DateTime::from_timestamp_micros(1_714_564_800_123_456).unwrap(), id: 42 }
+    }
+
+    fn is_invalid(cursor: &str) -> bool {
+        matches!(Cursor::parse(cursor), Err(Error::InvalidFilter(_)))
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(Cursor::parse(&cursor().to_string()).unwrap(), cursor());
+
+        let before_epoch: Cursor = Cursor { time_stamp: DateTime::from_timestamp_micros(-1).unwrap(), id: i64::MAX };
+        assert_eq!(Cursor::parse(&before_epoch.to_string()).unwrap(), before_epoch);
+    }
+
+    #[test]
+    fn cursors_are_url_safe() {
+        assert!(cursor().to_string().chars().all(|char| char.is_ascii_alphanumeric() || matches!(char, '-' | '_')));
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        assert!(is_invalid(""));
+        assert!(is_invalid("not a cursor"));
+        assert!(is_invalid(&format!("{}=", cursor())));
+        assert!(is_invalid(&BASE64_STANDARD.encode([0xff, b':', b'1'])));
+    }
+
+    #[test]
+    fn tampered_cursors_are_rejected() {
+        for decoded in ["", "1714564800123456", "1714564800123456:", ":42", "abc:42", "1714564800123456:x", "1714564800123456:42:1", "9223372036854775807:42"] {
+            assert!(is_invalid(&BASE64_URL_SAFE_NO_PAD.encode(decoded)), "{decoded:?} was accepted");
+        }
+    }
+}
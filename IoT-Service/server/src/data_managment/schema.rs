@@ -0,0 +1,386 @@
+//! Schemas describe the measurements a device sends, so new kinds of sensors can be added at runtime. Every schema
+//! lists its fields with type, unit and range, and the devices it applies to. Devices without a schema of their own
+//! use [`DEFAULT_SCHEMA`], which has the `temp` and `rpm` fields the service started out with.
+//!
+//! The measurements are stored as JSONB next to the fixed columns, see [`super::dataformat::DataFrame`]. Schemas are
+//! kept in a JSON file and managed through [`crate::connector::admin::SCHEMAS_ENDPOINT`].
+
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+
+use crate::error::Error;
+use crate::util::write_atomically;
+use super::dataformat::DataFrame;
+
+use std::fs::read_to_string;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{OnceLock, RwLock, RwLockReadGuard};
+
+
+/// Default location of the schema store, relative to the working directory of the server
+pub const SCHEMAS_PATH: &str = ".schemas";
+
+/// Applies to every device not listed in another schema
+pub const DEFAULT_SCHEMA: &str = "default";
+
+/// Keys of a dataframe that aren't measurements
+pub const RESERVED_FIELDS: [&str; 6] = ["device_id", "time_stamp", "message_id", "received_at", "skewed", "device_time_stamp"];
+
+const MAX_NAME_LENGTH: usize = 64;
+
+static SCHEMAS: OnceLock<SchemaStore> = OnceLock::new();
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schema {
+    pub name: String,
+    /// Devices sending dataframes of this schema. Ignored for the default schema.
+    #[serde(default)]
+    pub devices: BTreeSet<i32>,
+    pub fields: Vec<Field>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Field {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: FieldType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    /// Inclusive bounds for numbers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    #[serde(default = "default_required")]
+    pub required: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    Integer,
+    Float,
+    Boolean,
+    Text
+}
+
+/// Every schema by name
+#[derive(Debug)]
+pub struct Schemas {
+    schemas: BTreeMap<String, Schema>
+}
+
+/// The schemas together with the file backing them, loaded once at startup by [`init_schemas`]
+pub struct SchemaStore {
+    schemas: RwLock<Schemas>,
+    path: String
+}
+
+fn default_required() -> bool {
+    true
+}
+
+impl Default for Schemas {
+    fn default() -> Self {
+        let default: Schema = Schema {
+            name: DEFAULT_SCHEMA.to_string(),
+            devices: BTreeSet::new(),
+            fields: vec![
+                Field { name: "temp".to_string(), kind: FieldType::Integer, unit: None, min: Some(i16::MIN as f64), max: Some(i16::MAX as f64), required: true },
+                Field { name: "rpm".to_string(), kind: FieldType::Integer, unit: Some("rpm".to_string()), min: Some(i32::MIN as f64), max: Some(i32::MAX as f64), required: true }
+            ]
+        };
+
+        Self { schemas: BTreeMap::from([(default.name.clone(), default)]) }
+    }
+}
+
+/// Loads the schema store from `path`. Has to be called before the first request is served.
+pub fn init_schemas(path: &str) -> Result<(), Error> {
+    let store: SchemaStore = SchemaStore { schemas: RwLock::new(Schemas::load_or_default(path)?), path: path.to_string() };
+
+    SCHEMAS.set(store).map_err(|_| Error::SchemaStoreError("Schemas are already initialised".to_string()))
+}
+
+pub fn schemas() -> &'static SchemaStore {
+    SCHEMAS.get().expect("init_schemas has to be called before serving requests")
+}
+
+impl SchemaStore {
+    pub fn read(&self) -> RwLockReadGuard<'_, Schemas> {
+        self.schemas.read().expect("Implement Logging | schema store poisoned")
+    }
+
+    /// Adds the schema or replaces the one with the same name and saves the store. Returns `true` if it replaced one.
+    pub fn put(&self, schema: Schema) -> Result<bool, Error> {
+        let mut schemas = self.schemas.write().expect("Implement Logging | schema store poisoned");
+
+        schemas.check(&schema)?;
+
+        let name: String = schema.name.clone();
+        let previous: Option<Schema> = schemas.schemas.insert(name.clone(), schema);
+
+        if let Err(err) = schemas.save(&self.path) {
+            // Keep memory and file in sync
+            match previous {
+                Some(previous) => schemas.schemas.insert(name, previous),
+                None => schemas.schemas.remove(&name)
+            };
+
+            return Err(err);
+        }
+
+        Ok(previous.is_some())
+    }
+
+    /// Removes the schema and saves the store. Returns `false` if there was none with this name.
+    pub fn remove(&self, name: &str) -> Result<bool, Error> {
+        if name == DEFAULT_SCHEMA {
+            return Err(Error::SchemaStoreError("The default schema can be replaced, but not removed".to_string()));
+        }
+
+        let mut schemas = self.schemas.write().expect("Implement Logging | schema store poisoned");
+
+        let Some(removed) = schemas.schemas.remove(name) else {
+            return Ok(false);
+        };
+
+        if let Err(err) = schemas.save(&self.path) {
+            schemas.schemas.insert(removed.name.clone(), removed);
+            return Err(err);
+        }
+
+        Ok(true)
+    }
+}
+
+impl Schemas {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let file: String = read_to_string(path).map_err(|err| Error::SchemaStoreError(format!("{path}: {err}")))?;
+        let list: Vec<Schema> = serde_json::from_str(&file).map_err(|err| Error::SchemaStoreError(format!("{path}: {err}")))?;
+
+        let mut schemas: Self = Self { schemas: BTreeMap::new() };
+
+        for schema in list {
+            schemas.check(&schema).map_err(|err| Error::SchemaStoreError(format!("{path}: {err}")))?;
+            schemas.schemas.insert(schema.name.clone(), schema);
+        }
+
+        if !schemas.schemas.contains_key(DEFAULT_SCHEMA) {
+            return Err(Error::SchemaStoreError(format!("{path}: The `{DEFAULT_SCHEMA}` schema is missing")));
+        }
+
+        Ok(schemas)
+    }
+
+    /// Loads the store from `path`, or returns the default schema only if the file doesn't exist yet
+    pub fn load_or_default(path: &str) -> Result<Self, Error> {
+        if std::path::Path::new(path).exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        let list: Vec<&Schema> = self.schemas.values().collect();
+        let file: String = serde_json::to_string_pretty(&list).map_err(|err| Error::SchemaStoreError(err.to_string()))?;
+
+        write_atomically(path, &file).map_err(|err| Error::SchemaStoreError(format!("{path}: {err}")))
+    }
+
+    pub fn list(&self) -> Vec<Schema> {
+        self.schemas.values().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Schema> {
+        self.schemas.get(name)
+    }
+
+    /// Whether any schema has a field with this name
+    pub fn declares(&self, field: &str) -> bool {
+        self.schemas.values().any(|schema| schema.field(field).is_some())
+    }
+
+    /// The schema the device sends its dataframes in
+    pub fn of_device(&self, device_id: i32) -> &Schema {
+        self.schemas.values()
+            .find(|schema| schema.name != DEFAULT_SCHEMA && schema.devices.contains(&device_id))
+            .unwrap_or(&self.schemas[DEFAULT_SCHEMA])
+    }
+
+    /// Checks the measurements of the dataframe against the schema of its device
+    pub fn validate(&self, dataframe: &DataFrame) -> Result<(), String> {
+        self.of_device(dataframe.device_id).validate(&dataframe.fields)
+    }
+
+    /// Checks whether the schema is well-formed and doesn't claim a device of another schema
+    fn check(&self, schema: &Schema) -> Result<(), Error> {
+        if !is_valid_name(&schema.name) {
+            return Err(Error::SchemaStoreError(format!("Invalid schema name `{}`, use up to {MAX_NAME_LENGTH} lowercase letters, digits and underscores", schema.name)));
+        }
+
+        let mut names: BTreeSet<&str> = BTreeSet::new();
+
+        for field in &schema.fields {
+            if !is_valid_name(&field.name) || RESERVED_FIELDS.contains(&field.name.as_str()) {
+                return Err(Error::SchemaStoreError(format!("Invalid field name `{}`", field.name)));
+            }
+
+            if !names.insert(&field.name) {
+                return Err(Error::SchemaStoreError(format!("Field `{}` is declared twice", field.name)));
+            }
+
+            let numeric: bool = matches!(field.kind, FieldType::Integer | FieldType::Float);
+
+            if !numeric && (field.min.is_some() || field.max.is_some()) {
+                return Err(Error::SchemaStoreError(format!("Field `{}`: min and max only apply to numbers", field.name)));
+            }
+
+            if let (Some(min), Some(max)) = (field.min, field.max) {
+                if min > max {
+                    return Err(Error::SchemaStoreError(format!("Field `{}`: min exceeds max", field.name)));
+                }
+            }
+        }
+
+        let claimed: Option<(&Schema, i32)> = self.schemas.values()
+            .filter(|other| other.name != schema.name && other.name != DEFAULT_SCHEMA)
+            .find_map(|other| other.devices.intersection(&schema.devices).next().map(|device_id| (other, *device_id)));
+
+        match claimed {
+            Some((other, device_id)) if schema.name != DEFAULT_SCHEMA => Err(Error::SchemaStoreError(format!("Device `{device_id}` already uses schema `{}`", other.name))),
+            _ => Ok(())
+        }
+    }
+}
+
+impl Schema {
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    pub fn validate(&self, values: &Map<String, Value>) -> Result<(), String> {
+        if let Some(unknown) = values.keys().find(|name| self.field(name).is_none()) {
+            return Err(format!("Unknown field `{unknown}` for schema `{}`", self.name));
+        }
+
+        for field in &self.fields {
+            match values.get(&field.name) {
+                None | Some(Value::Null) if field.required => return Err(format!("Field `{}` is missing", field.name)),
+                None | Some(Value::Null) => {},
+                Some(value) => field.validate(value)?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Field {
+    fn validate(&self, value: &Value) -> Result<(), String> {
+        let number: Option<f64> = match (self.kind, value) {
+            (FieldType::Integer, Value::Number(number)) if number.is_i64() || number.is_u64() => number.as_f64(),
+            (FieldType::Float, Value::Number(number)) => number.as_f64(),
+            (FieldType::Boolean, Value::Bool(_)) | (FieldType::Text, Value::String(_)) => None,
+            (kind, _) => return Err(format!("Field `{}` must be of type {}", self.name, serde_json::to_string(&kind).unwrap().trim_matches('"')))
+        };
+
+        let unit: String = self.unit.as_ref().map_or(String::new(), |unit| format!(" {unit}"));
+
+        match number {
+            Some(number) if self.min.is_some_and(|min| number < min) => Err(format!("Field `{}` must be at least {}{unit}", self.name, self.min.unwrap())),
+            Some(number) if self.max.is_some_and(|max| number > max) => Err(format!("Field `{}` must be at most {}{unit}", self.name, self.max.unwrap())),
+            _ => Ok(())
+        }
+    }
+}
+
+/// Names end up in JSON paths and filter expressions, so they are kept simple
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name.starts_with(|first: char| first.is_ascii_lowercase())
+        && name.chars().all(|char| char.is_ascii_lowercase() || char.is_ascii_digit() || char == '_')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+
+    fn values(value: Value) -> Map<String, Value> {
+        let Value::Object(values) = value else {
+            unreachable!()
+        };
+
+        values
+    }
+
+    fn schema() -> Schema {
+        Schema {
+            name: "weather".to_string(),
+            devices: BTreeSet::from([7]),
+            fields: vec![
+                Field { name: "temp".to_string(), kind: FieldType::Float, unit: Some("°C".to_string()), min: Some(-50.0), max: Some(60.0), required: true },
+                Field { name: "rain".to_string(), kind: FieldType::Integer, unit: None, min: None, max: None, required: false },
+                Field { name: "sunny".to_string(), kind: FieldType::Boolean, unit: None, min: None, max: None, required: false },
+                Field { name: "station".to_string(), kind: FieldType::Text, unit: None, min: None, max: None, required: false }
+            ]
+        }
+    }
+
+    #[test]
+    fn accepts_matching_values() {
+        assert_eq!(schema().validate(&values(json!({ "temp": 21.5, "rain": 3, "sunny": true, "station": "roof" }))), Ok(()));
+        assert_eq!(schema().validate(&values(json!({ "temp": -50 }))), Ok(()));
+    }
+
+    #[test]
+    fn requires_required_fields() {
+        assert_eq!(schema().validate(&values(json!({ "rain": 3 }))), Err("Field `temp` is missing".to_string()));
+        assert_eq!(schema().validate(&values(json!({ "temp": null }))), Err("Field `temp` is missing".to_string()));
+        assert_eq!(schema().validate(&values(json!({ "temp": 20, "rain": null }))), Ok(()));
+    }
+
+    #[test]
+    fn rejects_type_mismatches() {
+        assert_eq!(schema().validate(&values(json!({ "temp": "warm" }))), Err("Field `temp` must be of type float".to_string()));
+        assert_eq!(schema().validate(&values(json!({ "temp": 20, "rain": 1.5 }))), Err("Field `rain` must be of type integer".to_string()));
+        assert_eq!(schema().validate(&values(json!({ "temp": 20, "sunny": 1 }))), Err("Field `sunny` must be of type boolean".to_string()));
+        assert_eq!(schema().validate(&values(json!({ "temp": 20, "station": 4 }))), Err("Field `station` must be of type text".to_string()));
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        assert_eq!(schema().validate(&values(json!({ "temp": 60.5 }))), Err("Field `temp` must be at most 60 °C".to_string()));
+        assert_eq!(schema().validate(&values(json!({ "temp": -51 }))), Err("Field `temp` must be at least -50 °C".to_string()));
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert_eq!(schema().validate(&values(json!({ "temp": 20, "wind": 4 }))), Err("Unknown field `wind` for schema `weather`".to_string()));
+    }
+
+    #[test]
+    fn save_replaces_the_store() {
+        let path: String = std::env::temp_dir().join(format!("schemas_{}", std::process::id())).to_string_lossy().into_owned();
+
+        let mut schemas: Schemas = Schemas::default();
+        schemas.save(&path).unwrap();
+
+        schemas.schemas.insert("weather".to_string(), schema());
+        schemas.save(&path).unwrap();
+
+        let loaded: Schemas = Schemas::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(loaded.list().iter().map(|schema| schema.name.as_str()).collect::<Vec<&str>>(), ["default", "weather"]);
+        assert!(!std::path::Path::new(&format!("{path}.tmp")).exists());
+    }
+}
@@ -0,0 +1,136 @@
+//! Timestamps are accepted as RFC 3339 string, e.g. `2024-05-01T12:00:00.250+02:00`, as integer seconds since the unix
+//! epoch, e.g. `1714564800`, or as milliseconds since the unix epoch with the suffix `ms`, e.g. `"1714564800250ms"`,
+//! both in JSON and in query strings. Plain integers are always seconds, which is what devices sent before timestamps
+//! were stored as such. Timestamps are always returned as RFC 3339 in UTC.
+//!
+//! Timestamps outside the years [`MIN_YEAR`] to [`MAX_YEAR`] are rejected, which also catches milliseconds sent
+//! without the suffix.
+
+use chrono::{DateTime, Datelike, Utc};
+use serde::Deserializer;
+use serde::de::{self, Visitor};
+
+use std::fmt;
+
+
+pub type Timestamp = DateTime<Utc>;
+
+pub const MIN_YEAR: i32 = 1970;
+pub const MAX_YEAR: i32 = 2100;
+
+pub fn parse(value: &str) -> Result<Timestamp, String> {
+    let value: &str = value.trim();
+
+    if let Some(millis) = value.strip_suffix("ms") {
+        return match millis.parse::<i64>() {
+            Ok(millis) => from_millis(millis),
+            Err(_) => Err(format!("Invalid timestamp `{value}`, expected milliseconds since the unix epoch before `ms`"))
+        };
+    }
+
+    match value.parse::<i64>() {
+        Ok(seconds) => from_seconds(seconds),
+        Err(_) => DateTime::parse_from_rfc3339(value)
+            .map_err(|err| format!("Invalid timestamp `{value}`: {err}. Expected RFC 3339, seconds since the unix epoch or milliseconds followed by `ms`"))
+            .and_then(|timestamp| within_years(Some(timestamp.with_timezone(&Utc)), value))
+    }
+}
+
+pub fn from_seconds(seconds: i64) -> Result<Timestamp, String> {
+    within_years(DateTime::from_timestamp(seconds, 0), seconds)
+}
+
+pub fn from_millis(millis: i64) -> Result<Timestamp, String> {
+    within_years(DateTime::from_timestamp_millis(millis), format!("{millis}ms"))
+}
+
+fn within_years(timestamp: Option<Timestamp>, value: impl fmt::Display) -> Result<Timestamp, String> {
+    match timestamp {
+        Some(timestamp) if (MIN_YEAR..=MAX_YEAR).contains(&timestamp.year()) => Ok(timestamp),
+        _ => Err(format!(
+            "Timestamp `{value}` is not between the years {MIN_YEAR} and {MAX_YEAR}. Integers are seconds since the unix epoch, \
+             milliseconds need the suffix `ms`, e.g. \"1714564800000ms\""
+        ))
+    }
+}
+
+/// For use with `#[serde(deserialize_with = "...")]`
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Timestamp, D::Error> {
+    deserializer.deserialize_any(TimestampVisitor)
+}
+
+/// Like [`deserialize`], a missing field has to be handled with `#[serde(default)]`
+pub fn deserialize_option<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Timestamp>, D::Error> {
+    deserialize(deserializer).map(Some)
+}
+
+struct TimestampVisitor;
+
+impl Visitor<'_> for TimestampVisitor {
+    type Value = Timestamp;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an RFC 3339 timestamp, seconds since the unix epoch or milliseconds followed by `ms`")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        parse(value).map_err(E::custom)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        from_seconds(value).map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        i64::try_from(value).map_err(E::custom).and_then(|value| self.visit_i64(value))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn integers_are_seconds() {
+        assert_eq!(parse("22").unwrap(), DateTime::from_timestamp(22, 0).unwrap());
+        assert_eq!(parse(" 1714564800 ").unwrap(), DateTime::from_timestamp(1_714_564_800, 0).unwrap());
+        assert_eq!(serde_json::from_value::<Wrapper>(serde_json::json!({"at": 1714564800})).unwrap().at, parse("1714564800").unwrap());
+    }
+
+    #[test]
+    fn milliseconds_need_a_suffix() {
+        assert_eq!(parse("1714564800250ms").unwrap(), DateTime::from_timestamp_millis(1_714_564_800_250).unwrap());
+        assert_eq!(serde_json::from_value::<Wrapper>(serde_json::json!({"at": "1714564800250ms"})).unwrap().at, parse("1714564800250ms").unwrap());
+
+        assert!(parse("ms").unwrap_err().starts_with("Invalid timestamp `ms`"));
+        assert!(parse("1.5ms").unwrap_err().starts_with("Invalid timestamp `1.5ms`"));
+    }
+
+    #[test]
+    fn rfc_3339_is_converted_to_utc() {
+        assert_eq!(parse("2024-05-01T12:00:00.250+02:00").unwrap(), DateTime::from_timestamp_millis(1_714_557_600_250).unwrap());
+        assert!(parse("2024-05-01").unwrap_err().starts_with("Invalid timestamp `2024-05-01`"));
+    }
+
+    #[test]
+    fn years_out_of_range_are_rejected() {
+        // Milliseconds without the suffix
+        assert!(parse("1714564800000").unwrap_err().starts_with("Timestamp `1714564800000` is not between the years 1970 and 2100"));
+        assert!(serde_json::from_value::<Wrapper>(serde_json::json!({"at": 1714564800000_i64})).is_err());
+
+        assert!(parse("-1").is_err());
+        assert!(parse("-1ms").is_err());
+        assert!(parse("1969-12-31T23:59:59Z").is_err());
+        assert!(parse("2101-01-01T00:00:00Z").is_err());
+        assert!(parse(&i64::MAX.to_string()).is_err());
+        assert!(parse("2100-12-31T23:59:59Z").is_ok());
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Wrapper {
+        #[serde(deserialize_with = "deserialize")]
+        at: Timestamp
+    }
+}
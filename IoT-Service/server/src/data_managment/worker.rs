@@ -0,0 +1,444 @@
+//! Pool of blocking database workers. Every worker owns its own connection and takes messages from the shared queue,
+//! so a slow query only blocks the worker running it.
+
+use crossbeam::channel::{select, Receiver, Sender, TrySendError};
+use hyper::StatusCode;
+use hyper::body::Bytes;
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, oneshot, Notify, SemaphorePermit};
+use tokio::time;
+use postgres::Client;
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::util::init_db;
+use crate::config::{DatabaseConfig, WorkerConfig};
+use super::migrations::migrate;
+use super::filter::Filter;
+use super::dataformat::DataFrame;
+use super::database::{aggregate, delete, get_page, insert, insert_all, update, Export};
+use super::message_passing::{self, ExportChunk, Message, ResponseMessage};
+
+use std::thread::{self, JoinHandle};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+
+/// Dataframes fetched from the portal of an export at once
+const EXPORT_CHUNK_ROWS: i32 = 1000;
+
+/// Longest time an export waits for the client to take the next chunk. Socket buffers hold several megabytes, which a
+/// slow client has to work through before the next chunk fits, so this is generous. How many workers slow clients can
+/// hold at once is limited by `worker.max_exports`.
+const EXPORT_SEND_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Set by [`stop_exports`]
+static EXPORTS_STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// Wakes the exports waiting for their client once [`EXPORTS_STOPPED`] is set
+static EXPORTS_STOPPING: Notify = Notify::const_new();
+
+pub static METRICS: Metrics = Metrics {
+    workers: AtomicUsize::new(0),
+    busy_workers: AtomicUsize::new(0),
+    processed: AtomicU64::new(0),
+    rejected: AtomicU64::new(0),
+    batches: AtomicU64::new(0),
+    inserted: AtomicU64::new(0),
+    duplicates: AtomicU64::new(0),
+    failed_inserts: AtomicU64::new(0)
+};
+
+pub struct Metrics {
+    workers: AtomicUsize,
+    busy_workers: AtomicUsize,
+    processed: AtomicU64,
+    /// Messages turned away because the queue was full
+    rejected: AtomicU64,
+    batches: AtomicU64,
+    inserted: AtomicU64,
+    /// Dataframes dropped because their message ID was already stored
+    duplicates: AtomicU64,
+    failed_inserts: AtomicU64
+}
+
+/// Snapshot of [`METRICS`] together with the state of the queue
+#[derive(Debug, Serialize)]
+pub struct QueueMetrics {
+    pub queue_depth: usize,
+    pub queue_capacity: Option<usize>,
+    pub workers: usize,
+    pub busy_workers: usize,
+    pub processed: u64,
+    pub rejected: u64,
+    pub batches: u64,
+    pub inserted: u64,
+    pub duplicates: u64,
+    pub failed_inserts: u64
+}
+
+impl Metrics {
+    pub fn snapshot(&self, sender: &Sender<Message>) -> QueueMetrics {
+        QueueMetrics {
+            queue_depth: sender.len(),
+            queue_capacity: sender.capacity(),
+            workers: self.workers.load(Ordering::Relaxed),
+            busy_workers: self.busy_workers.load(Ordering::Relaxed),
+            processed: self.processed.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            batches: self.batches.load(Ordering::Relaxed),
+            inserted: self.inserted.load(Ordering::Relaxed),
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+            failed_inserts: self.failed_inserts.load(Ordering::Relaxed)
+        }
+    }
+}
+
+/// Queues a message without waiting. A full queue means the workers can't keep up, in which case the request is
+/// rejected right away instead of piling up.
+pub fn dispatch(sender: &Sender<Message>, message: Message) -> Result<(), Error> {
+    match sender.try_send(message) {
+        Ok(()) => Ok(()),
+        Err(TrySendError::Full(_)) => {
+            METRICS.rejected.fetch_add(1, Ordering::Relaxed);
+            Err(Error::QueueFull)
+        },
+        Err(TrySendError::Disconnected(_)) => Err(Error::WorkerUnavailable)
+    }
+}
+
+/// Connects `worker.pool_size` clients, migrates the schema and starts one thread per client. The threads block until
+/// a message arrives. Once every sender is dropped, they finish the messages still queued and exit. Closing `shutdown`
+/// does the same while senders are left, e.g. in connections that outlived the shutdown timeout.
+///
+/// Has to be called from within the runtime serving the requests, which the workers use to wait for export clients.
+pub fn spawn_pool(database: &DatabaseConfig, worker: &WorkerConfig, receiver: Receiver<Message>, shutdown: Receiver<()>) -> Result<Vec<JoinHandle<()>>, Error> {
+    let mut clients: Vec<Client> = Vec::with_capacity(worker.pool_size);
+
+    for _ in 0..worker.pool_size {
+        clients.push(init_db(database, worker)?);
+    }
+
+    migrate(&mut clients[0])?;
+
+    let runtime: Handle = Handle::current();
+
+    let handles: Vec<JoinHandle<()>> = clients.into_iter()
+        .enumerate()
+        .map(|(id, client)| {
+            let receiver: Receiver<Message> = receiver.clone();
+            let shutdown: Receiver<()> = shutdown.clone();
+            let worker: WorkerConfig = worker.clone();
+            let runtime: Handle = runtime.clone();
+
+            thread::Builder::new()
+                .name(format!("worker-{id}"))
+                .spawn(move || work(client, receiver, shutdown, worker, runtime))
+                .expect("Implement Logging | Failed to spawn worker")
+        })
+        .collect();
+
+    METRICS.workers.store(handles.len(), Ordering::Relaxed);
+
+    Ok(handles)
+}
+
+fn work(mut client: Client, receiver: Receiver<Message>, shutdown: Receiver<()>, worker: WorkerConfig, runtime: Handle) {
+    let batch_delay: Duration = Duration::from_millis(worker.batch_delay_ms);
+
+    loop {
+        select! {
+            recv(receiver) -> message => match message {
+                Ok(Message::Post(dataframe, reply)) => {
+                    METRICS.busy_workers.fetch_add(1, Ordering::Relaxed);
+
+                    let (batch, next) = collect_batch((dataframe, reply), &receiver, worker.batch_size, batch_delay);
+                    METRICS.processed.fetch_add(batch.len() as u64, Ordering::Relaxed);
+                    insert_batch(batch, &mut client);
+
+                    // The message that ended the batch, handled after it to keep the order
+                    if let Some(message) = next {
+                        handle(message, &mut client, &runtime);
+                        METRICS.processed.fetch_add(1, Ordering::Relaxed);
+                    }
+
+                    METRICS.busy_workers.fetch_sub(1, Ordering::Relaxed);
+                },
+                Ok(message) => {
+                    METRICS.busy_workers.fetch_add(1, Ordering::Relaxed);
+                    handle(message, &mut client, &runtime);
+                    METRICS.busy_workers.fetch_sub(1, Ordering::Relaxed);
+                    METRICS.processed.fetch_add(1, Ordering::Relaxed);
+                },
+                Err(_) => break
+            },
+            // Nothing is ever sent on the shutdown channel, it only gets disconnected
+            recv(shutdown) -> _ => {
+                while let Ok(message) = receiver.try_recv() {
+                    handle(message, &mut client, &runtime);
+                    METRICS.processed.fetch_add(1, Ordering::Relaxed);
+                }
+
+                break;
+            }
+        }
+    }
+
+    if let Err(err) = client.close() {
+        eprintln!("Failed to close database connection: {err}");
+    }
+
+    METRICS.workers.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// A dataframe waiting to be inserted, together with the channel to report the outcome on, if the client waits for it
+type Pending = (DataFrame, Option<oneshot::Sender<Box<dyn message_passing::Response>>>);
+
+/// Takes further dataframes from the queue until the batch is full, `delay` has passed since the first one or another
+/// kind of message comes up. That message is returned, so it can be handled after the batch.
+fn collect_batch(first: Pending, receiver: &Receiver<Message>, size: usize, delay: Duration) -> (Vec<Pending>, Option<Message>) {
+    let deadline: Instant = Instant::now() + delay;
+    let mut batch: Vec<Pending> = vec![first];
+
+    while batch.len() < size {
+        // Behaves like try_recv once the deadline has passed, so queued dataframes are still taken
+        match receiver.recv_deadline(deadline) {
+            Ok(Message::Post(dataframe, reply)) => batch.push((dataframe, reply)),
+            Ok(message) => return (batch, Some(message)),
+            Err(_) => break
+        }
+    }
+
+    (batch, None)
+}
+
+/// Inserts the batch and reports the outcome of every dataframe to its sender
+fn insert_batch(batch: Vec<Pending>, client: &mut Client) {
+    let (dataframes, replies): (Vec<DataFrame>, Vec<_>) = batch.into_iter().unzip();
+
+    for (result, reply) in try_insert_batch(&dataframes, client).into_iter().zip(replies) {
+        let Some(reply) = reply else {
+            continue;
+        };
+
+        // A duplicate gets the same answer as the original, which was stored successfully
+        let response: Box<dyn message_passing::Response> = match result {
+            Ok(_) => Box::new(ResponseMessage::new("", StatusCode::OK)),
+            Err(err) => Box::new(ResponseMessage::new(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR))
+        };
+
+        let _ = reply.send(response);
+    }
+}
+
+/// Inserts the batch in one statement. If that fails, the rows are inserted one by one, so a single invalid row doesn't
+/// take the rest of the batch with it. Returns one result per dataframe, `false` for a duplicate.
+fn try_insert_batch(batch: &[DataFrame], client: &mut Client) -> Vec<Result<bool, Error>> {
+    METRICS.batches.fetch_add(1, Ordering::Relaxed);
+
+    let err: Error = match insert(batch, client) {
+        Ok(inserted) => {
+            count_inserted(&inserted);
+            return inserted.into_iter().map(Ok).collect();
+        },
+        Err(err) => err
+    };
+
+    if batch.len() == 1 {
+        METRICS.failed_inserts.fetch_add(1, Ordering::Relaxed);
+        eprintln!("Insertion failed: {err}");
+        return vec![Err(err)];
+    }
+
+    eprintln!("Batch of {} dataframes failed, retrying one by one: {err}", batch.len());
+
+    let results: Vec<Result<bool, Error>> = batch.iter()
+        .map(|dataframe| match insert(std::slice::from_ref(dataframe), client) {
+            Ok(inserted) => {
+                count_inserted(&inserted);
+                Ok(inserted[0])
+            },
+            Err(err) => {
+                METRICS.failed_inserts.fetch_add(1, Ordering::Relaxed);
+                eprintln!("Insertion of {dataframe:?} failed: {err}");
+                Err(err)
+            }
+        })
+        .collect();
+
+    let failed: usize = results.iter().filter(|result| result.is_err()).count();
+    eprintln!("Batch of {} dataframes: {} inserted, {failed} failed", batch.len(), batch.len() - failed);
+
+    results
+}
+
+fn count_inserted(inserted: &[bool]) {
+    let stored: u64 = inserted.iter().filter(|inserted| **inserted).count() as u64;
+
+    METRICS.inserted.fetch_add(stored, Ordering::Relaxed);
+    METRICS.duplicates.fetch_add(inserted.len() as u64 - stored, Ordering::Relaxed);
+}
+
+fn handle(message: Message, client: &mut Client, runtime: &Handle) {
+    match message {
+        // Dataframes are always inserted in batches, see insert_batch
+        Message::Post(dataframe, reply) => insert_batch(vec![(dataframe, reply)], client),
+        Message::Get(ref filter, ref request, response_channel) => {
+            let response: Box<dyn message_passing::Response> = match get_page(filter, request, client) {
+                Ok(page) => Box::new(ResponseMessage::new(serde_json::to_string(&page).unwrap(), StatusCode::OK)),
+                Err(err) => {
+                    eprintln!("Query failed: {err}");
+                    Box::new(ResponseMessage::new(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR))
+                }
+            };
+
+            let _ = response_channel.send(response);
+        },
+        Message::Export(ref filter, response_channel, chunks, slot) => export(filter, response_channel, chunks, slot, client, runtime),
+        Message::Aggregate(ref aggregation, response_channel) => {
+            let response: Box<dyn message_passing::Response> = match aggregate(aggregation, client) {
+                Ok(buckets) => Box::new(ResponseMessage::new(serde_json::to_string(&buckets).unwrap(), StatusCode::OK)),
+                Err(err) => {
+                    eprintln!("Aggregation failed: {err}");
+                    Box::new(ResponseMessage::new(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR))
+                }
+            };
+
+            let _ = response_channel.send(response);
+        },
+        Message::Delete(ref filter, response_channel) => {
+            let response: Box<dyn message_passing::Response> = match delete(filter, client) {
+                Ok(_) => Box::new(message_passing::ResponseMessage::new("", StatusCode::OK)),
+                Err(err) => {
+                    let (status_code, content) = match err {
+                        Error::DatabaseDeletionError(inner) => (StatusCode::BAD_REQUEST, inner),
+                        _ => (StatusCode::INTERNAL_SERVER_ERROR, String::from("Undefined Behaviour"))
+                    };
+
+                    Box::new(message_passing::ResponseMessage::new(content, status_code))
+                }
+            };
+            let _ = response_channel.send(response);
+        },
+        Message::Put(ref filter, ref dataframe, response_channel) => {
+            let response: Box<dyn message_passing::Response> = match update(filter, dataframe, client) {
+                Ok(_) => Box::new(message_passing::ResponseMessage::new("", StatusCode::OK)),
+                Err(err) => {
+                    let (status_code, content) = match err {
+                        Error::DatabaseUpdateError(inner) => (StatusCode::BAD_REQUEST, inner),
+                        _ => (StatusCode::INTERNAL_SERVER_ERROR, String::from("Undefined Behaviour"))
+                    };
+
+                    Box::new(message_passing::ResponseMessage::new(content, status_code))
+                }
+            };
+            let _ = response_channel.send(response);
+        },
+        Message::Bulk(ref dataframes, response_channel) => {
+            METRICS.batches.fetch_add(1, Ordering::Relaxed);
+
+            // Replies with the positions of the duplicates as JSON array
+            let response: Box<dyn message_passing::Response> = match insert_all(dataframes, client) {
+                Ok(inserted) => {
+                    count_inserted(&inserted);
+
+                    let duplicates: Vec<usize> = inserted.iter().enumerate().filter(|(_, inserted)| !**inserted).map(|(index, _)| index).collect();
+                    Box::new(message_passing::ResponseMessage::new(serde_json::to_string(&duplicates).unwrap(), StatusCode::OK))
+                },
+                Err(err) => {
+                    METRICS.failed_inserts.fetch_add(dataframes.len() as u64, Ordering::Relaxed);
+                    Box::new(message_passing::ResponseMessage::new(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR))
+                }
+            };
+            let _ = response_channel.send(response);
+        }
+    }
+}
+
+/// Sends the dataframes chunk by chunk. Blocks while the channel is full, so a slow client holds the worker, but the
+/// memory used stays the same regardless of the size of the export. The slot is released once the export is closed.
+fn export(
+    filter: &Filter,
+    response_channel: oneshot::Sender<Box<dyn message_passing::Response>>,
+    chunks: mpsc::Sender<ExportChunk>,
+    slot: SemaphorePermit<'static>,
+    client: &mut Client,
+    runtime: &Handle
+) {
+    let mut export: Export = match Export::start(filter, client) {
+        Ok(export) => export,
+        Err(err) => {
+            eprintln!("Export failed: {err}");
+            let _ = response_channel.send(Box::new(ResponseMessage::new(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)));
+            return;
+        }
+    };
+
+    if response_channel.send(Box::new(ResponseMessage::new("", StatusCode::OK))).is_ok() {
+        loop {
+            let chunk: ExportChunk = match export.next_chunk(EXPORT_CHUNK_ROWS) {
+                Ok(dataframes) if dataframes.is_empty() => ExportChunk::End,
+                Ok(dataframes) => {
+                    let mut chunk: String = String::new();
+
+                    for dataframe in dataframes {
+                        chunk.push_str(&serde_json::to_string(&dataframe).unwrap());
+                        chunk.push('\n');
+                    }
+
+                    ExportChunk::Data(Bytes::from(chunk))
+                },
+                Err(err) => {
+                    eprintln!("Export failed: {err}");
+                    ExportChunk::Failed(err)
+                }
+            };
+
+            let last: bool = !matches!(chunk, ExportChunk::Data(_));
+
+            if !send_chunk(&chunks, chunk, runtime) || last {
+                break;
+            }
+        }
+    }
+
+    if let Err(err) = export.close() {
+        eprintln!("Failed to close export: {err}");
+    }
+
+    drop(slot);
+}
+
+/// Ends running exports before their next chunk. Called on shutdown once the connections had their time, so downloads
+/// still running don't keep the workers from draining the queue.
+pub fn stop_exports() {
+    EXPORTS_STOPPED.store(true, Ordering::Relaxed);
+    EXPORTS_STOPPING.notify_waiters();
+}
+
+/// Waits up to [`EXPORT_SEND_TIMEOUT`] for room in the channel. Returns `false` if the client is gone or stopped
+/// reading, so a stalled download can't hold the worker forever, or if exports were stopped.
+fn send_chunk(chunks: &mpsc::Sender<ExportChunk>, chunk: ExportChunk, runtime: &Handle) -> bool {
+    runtime.block_on(async {
+        let stopping = EXPORTS_STOPPING.notified();
+        tokio::pin!(stopping);
+
+        // Registered before checking the flag, so a stop in between still wakes the export
+        stopping.as_mut().enable();
+
+        if EXPORTS_STOPPED.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        tokio::select! {
+            sent = time::timeout(EXPORT_SEND_TIMEOUT, chunks.send(chunk)) => match sent {
+                Ok(sent) => sent.is_ok(),
+                Err(_) => {
+                    eprintln!("Export aborted, the client took no data for {}s", EXPORT_SEND_TIMEOUT.as_secs());
+                    false
+                }
+            },
+            _ = stopping => false
+        }
+    })
+}
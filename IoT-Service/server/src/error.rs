@@ -1,19 +1,46 @@
+use std::fmt;
+
+#[derive(Debug)]
 pub enum Error {
     FailedHeaderConversion,
     InvalidAuthentificationFormat,
     DecodeError,
     ConversionError,
     TransmissionError,
+    /// The body exceeds `server.max_body_size`
+    PayloadTooLarge,
     InvalidDataFormat,
     QueryParsingError,
     QueryNotProvided,
     QueryInvalidAPI,
     DatabaseQueryNotSupported,
     DatabaseQueryInvalidFormat,
+    InvalidFilter(String),
     DatabaseConfigError(String),
+    DatabaseConnectionError(String),
+    DatabaseMigrationError(String),
     DatabaseQueryFailed(String),
+    DatabaseInsertionError(String),
     DatabaseDeletionError(String),
-    DatabaseUpdateError(String)
+    DatabaseUpdateError(String),
+    CredentialStoreError(String),
+    PasswordHashingError(String),
+    DeviceRegistryError(String),
+    ApiKeyStoreError(String),
+    SchemaStoreError(String),
+    AuthentificationMissing,
+    DeviceSecretStoreError(String),
+    InvalidSignature,
+    StaleSignature,
+    ReplayedSignature,
+    NonceCacheFull,
+    TlsConfigError(String),
+    /// Seconds until the next attempt is allowed
+    LockedOut(u64),
+    ConfigError(String),
+    /// The worker queue is full
+    QueueFull,
+    WorkerUnavailable
 }
 
 pub trait Logging {
@@ -24,3 +51,27 @@ pub trait Logging {
 
     fn log_to_file(self, path: &Self::Path) -> Self::Output;
 }
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DatabaseConfigError(msg)
+            | Self::InvalidFilter(msg)
+            | Self::DatabaseConnectionError(msg)
+            | Self::DatabaseMigrationError(msg)
+            | Self::DatabaseQueryFailed(msg)
+            | Self::DatabaseInsertionError(msg)
+            | Self::DatabaseDeletionError(msg)
+            | Self::DatabaseUpdateError(msg)
+            | Self::CredentialStoreError(msg)
+            | Self::PasswordHashingError(msg)
+            | Self::DeviceRegistryError(msg)
+            | Self::ApiKeyStoreError(msg)
+            | Self::SchemaStoreError(msg)
+            | Self::DeviceSecretStoreError(msg)
+            | Self::TlsConfigError(msg)
+            | Self::ConfigError(msg) => write!(f, "{msg}"),
+            _ => write!(f, "{self:?}")
+        }
+    }
+}
@@ -1,3 +1,6 @@
+pub mod authentification;
+pub mod cli;
+pub mod config;
 pub mod connector;
 pub mod data_managment;
 pub mod error;
@@ -1,167 +1,264 @@
-use hyper::header::{HeaderValue, AUTHORIZATION};
+use hyper::server::conn::Http;
 use hyper::service::{make_service_fn, service_fn};
+use hyper::header::RETRY_AFTER;
+use hyper::server::conn::AddrStream;
 use hyper::{Body, Request, Response, Server, StatusCode};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio::time::{timeout_at, Instant};
 
-use rayon::prelude::*;
-use crossbeam::channel::{Sender, unbounded};
-
-use crossterm::event::read;
-use crossterm::event::{poll, Event, KeyCode, KeyEvent};
+use crossbeam::channel::{Sender, bounded};
 
+use server::cli::{split_flags, Command};
+use server::config::{Config, DatabaseConfig};
 use server::error::Error;
-use postgres::Client;
-use server::util::{init_db, terminate_db};
-use server::data_managment::database::{delete, get, update};
+use server::util::{start_embedded_db, stop_embedded_db};
+use server::data_managment::clock::init_clocks;
+use server::data_managment::page::init_pages;
+use server::data_managment::schema::init_schemas;
+use server::data_managment::worker::{spawn_pool, stop_exports};
 
 use std::thread;
 use std::sync::Arc;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::convert::Infallible;
 use std::time::Duration;
 
-use server::data_managment::dataformat::DataFrame;
-use server::data_managment::message_passing::{self, Message, ResponseMessage};
-use server::connector::{response::build_response, middleware::{auth, serve_request}};
+use server::data_managment::message_passing::Message;
+use server::connector::body::{init_body_limit, payload_too_large};
+use server::connector::export::init_export_limit;
+use server::connector::{response::build_response, middleware::{auth, init_authentification, serve_request}};
+use server::connector::tls::{client_common_name, ReloadingTlsAcceptor};
 
 #[tokio::main]
 async fn main() {
-    let addr: SocketAddr = SocketAddr::from(([127, 0, 0, 1], 3000));
-
-    let (sender, receiver) = unbounded::<Message>();
-    let sender: Arc<Sender<Message>> = Arc::new(sender.clone());
-
-    let working: thread::JoinHandle<()> = thread::spawn(move || {
-        let mut client: Client = init_db().expect("Implement logging");
-        /*
-
-        if let Ok(k) = client.execute(
-            r"CREATE TABLE Dataframe (
-                temp            smallint,
-                rpm             int,
-                device_id       int,
-                time_stamp      int
-            )" , &[]) {
-            println!("{k}")
-        };
+    let (args, flags) = split_flags(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
 
-        */
+    let config: Config = Config::load(&flags).unwrap_or_else(|err| {
+        eprintln!("Invalid configuration: {err}");
+        std::process::exit(1);
+    });
 
-        loop {
-            if let Ok(true) = poll(Duration::from_millis(0)) {
-                if let Ok(Event::Key(KeyEvent {code: KeyCode::Char('q'), ..})) = read() {
-                    break;
-                }
-            }
+    if let Some(command) = Command::parse(&args) {
+        // Commands use the blocking postgres client, which must not run on a runtime thread
+        if let Err(err) = tokio::task::block_in_place(|| command.run(&config)) {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Err(err) = init_authentification(&config.auth)
+        .and_then(|()| init_body_limit(&config.server))
+        .and_then(|()| init_export_limit(&config.worker))
+        .and_then(|()| init_clocks(&config.clock))
+        .and_then(|()| init_schemas(&config.database.schemas))
+        .and_then(|()| init_pages(&config.query)) {
+        eprintln!("Invalid configuration: {err}");
+        std::process::exit(1);
+    }
+
+    let addr: SocketAddr = config.socket_addr();
+
+    let (sender, receiver) = bounded::<Message>(config.worker.queue_capacity);
+    let sender: Arc<Sender<Message>> = Arc::new(sender);
+
+    // Never sent on, dropping the sender stops every worker without draining the queue
+    let (shutdown_sender, shutdown_receiver) = bounded::<()>(0);
+
+    let database: DatabaseConfig = config.database.clone();
+
+    if database.embedded {
+        if let Err(err) = tokio::task::block_in_place(|| start_embedded_db(&database)) {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    }
+
+    // Connecting uses the blocking postgres client, which must not run on a runtime thread
+    let workers: Vec<thread::JoinHandle<()>> = tokio::task::block_in_place(|| spawn_pool(&database, &config.worker, receiver, shutdown_receiver))
+        .unwrap_or_else(|err| {
+            eprintln!("{err}");
+            std::process::exit(1);
+        });
+
+    // Flips to true once a shutdown signal arrives, after which no new connections are accepted
+    let (stop_accepting, accepting) = watch::channel(false);
+    let shutdown_timeout: Duration = Duration::from_secs(config.server.shutdown_timeout);
 
-            receiver.try_iter().for_each(|message| {
-                match message {
-                    Message::Post(dataframe) => {
-                        let DataFrame {temp, rpm, device_id, time_stamp} = dataframe;
-
-                        //"INSERT INTO author (name, country) VALUES ($1, $2)",
-                        // &[&author.name, &author.country]
-
-                        let query: &str = "INSERT INTO Dataframe (temp, rpm, device_id, time_stamp) VALUES ($1, $2, $3, $4)";
-
-                        client.execute(query, &[&temp, &rpm, &device_id, &time_stamp]).expect("Implement logging | Insertion failed");
-                    },
-                    Message::Get(ref query, response_channel) => {
-                        let response: Result<Vec<DataFrame>, Error> = get(query, &mut client);
-                        let response: String = match response {
-                            Ok(query) => {
-                                let mut reponse: String = String::from("{\n\t \"response\": [");
-
-                                for dataframe in query {
-                                    reponse.push_str(&format!("\n\t\t{},", serde_json::to_string(&dataframe).unwrap()))
-                                }
-
-                                // remove the last ","
-                                reponse.remove(reponse.len() - 1);
-
-                                reponse.push_str("\n\t]");
-                                reponse.push_str("\n}");
-
-                                reponse
-                            },
-                            Err(err) => match err {
-                                Error::DatabaseQueryFailed(err) => err,
-                                _ => "Unknown Error".to_string()
-                            }
-                        };
-
-                        let _ = response_channel.send(Box::new(ResponseMessage::new(response, StatusCode::OK)));
-                    },
-                    Message::Delete(ref query, response_channel) => {
-                        let response: Box<dyn message_passing::Response> = match delete(query, &mut client) {
-                            Ok(()) => Box::new(message_passing::ResponseMessage::new("", StatusCode::OK)),
-                            Err(err) => {
-                                let (status_code, content) = match err {
-                                    Error::DatabaseDeletionError(inner) => (StatusCode::BAD_REQUEST, inner),
-                                    _ => (StatusCode::INTERNAL_SERVER_ERROR, String::from("Undefined Behaviour"))
-                                };
-
-                                Box::new(message_passing::ResponseMessage::new(content, status_code))
-                            }
-                        };
-                        let _ = response_channel.send(response);
-                    },
-                    Message::Put(ref query, ref dataframe, response_channel) => {
-                        let response: Box<dyn message_passing::Response> = match update(query, dataframe, &mut client) {
-                            Ok(()) => Box::new(message_passing::ResponseMessage::new("", StatusCode::OK)),
-                            Err(err) => {
-                                let (status_code, content) = match err {
-                                    Error::DatabaseUpdateError(inner) => (StatusCode::BAD_REQUEST, inner),
-                                    _ => (StatusCode::INTERNAL_SERVER_ERROR, String::from("Undefined Behaviour"))
-                                };
-
-                                Box::new(message_passing::ResponseMessage::new(content, status_code))
-                            }
-                        };
-                        let _ = response_channel.send(response);
-                    }
+    // Owns the last senders. Once it completes, the queue disconnects and the workers exit after draining it.
+    let serving = async {
+        if let Some(tls_settings) = config.tls_settings() {
+            let tls: ReloadingTlsAcceptor = ReloadingTlsAcceptor::new(tls_settings).unwrap_or_else(|err| {
+                eprintln!("Invalid configuration: {err}");
+                std::process::exit(1);
+            });
+            serve_tls(addr, tls, sender, accepting).await;
+        } else {
+            // Capture the sender and pass it to the service function
+            let make_svc = make_service_fn(move |conn: &AddrStream| {
+
+                // Find a way to optimize this clone away
+                let sender: Arc<Sender<Message>> = sender.clone();
+                let remote_ip: IpAddr = conn.remote_addr().ip();
+
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                        handle_request(req, sender.clone(), None, remote_ip)
+                    }))
                 }
-            })
+            });
+
+            let mut accepting: watch::Receiver<bool> = accepting;
+            let server = Server::bind(&addr).serve(make_svc).with_graceful_shutdown(async move { stopped(&mut accepting).await });
+
+            if let Err(e) = server.await {
+                eprintln!("server error: {}", e);
+            }
         }
-        print!("Terminating IoT-Service...");
-        terminate_db().expect("Implement logging");
-        println!("Done");
+    };
+    tokio::pin!(serving);
 
-        std::process::exit(0);
-    });
+    tokio::select! {
+        _ = &mut serving => {},
+        _ = shutdown_signal() => {}
+    }
 
-    // Capture the sender and pass it to the service function
-    let make_svc = make_service_fn(move |_conn| {
+    println!("Terminating IoT-Service, waiting up to {}s for pending requests...", shutdown_timeout.as_secs());
+    let serving_deadline: Instant = Instant::now() + shutdown_timeout;
 
-        // Find a way to optimize this clone away
-        let sender: Arc<Sender<Message>> = sender.clone();
+    let _ = stop_accepting.send(true);
+
+    if timeout_at(serving_deadline, &mut serving).await.is_err() {
+        eprintln!("Connections still open after {}s, closing them", shutdown_timeout.as_secs());
+    }
 
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
-                handle_request(req, sender.clone())
-            }))
+    // Connections that didn't finish in time may still hold a sender, so the workers are told explicitly to finish what
+    // is queued and exit
+    stop_exports();
+    drop(shutdown_sender);
+
+    // The queue may only just have been filled by the last requests, so draining it gets a budget of its own
+    println!("Waiting up to {}s for queued messages...", shutdown_timeout.as_secs());
+    let draining_deadline: Instant = Instant::now() + shutdown_timeout;
+
+    let joining = tokio::task::spawn_blocking(move || {
+        for worker in workers {
+            let _ = worker.join();
         }
     });
 
-    let server = Server::bind(&addr).serve(make_svc);
-
-    if let Err(e) = server.await {
-        eprintln!("server error: {}", e);
+    if timeout_at(draining_deadline, joining).await.is_err() {
+        eprintln!("Workers didn't finish within {}s, queued messages are lost", shutdown_timeout.as_secs());
+        std::process::exit(1);
     }
 
-    working.join().unwrap();
+    if database.embedded {
+        tokio::task::block_in_place(|| stop_embedded_db(&database)).expect("Implement logging");
+    }
+    println!("Done");
 }
 
-async fn handle_request(req: Request<Body>, sender: Arc<Sender<Message>>) -> Result<Response<Body>, Infallible> {
-    let auth_header: Option<&HeaderValue> = req.headers().get(AUTHORIZATION);
+/// Resolves on SIGINT (Ctrl+C) or SIGTERM, the latter being what container runtimes and service managers send
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
 
-    if let Some(auth_header) = auth_header {
-        if let Ok(true) = auth(auth_header) {
-            // Now you have access to the sender
-            Ok(serve_request(req, sender).await)
-        } else {
-            Ok(build_response(StatusCode::UNAUTHORIZED, "Password and/or Username don't match".to_string()).expect("Implement Logging"))
+        let mut terminate = signal(SignalKind::terminate()).expect("Implement Logging | Failed to install SIGTERM handler");
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = terminate.recv() => {}
         }
-    } else {
-        Ok(build_response(StatusCode::UNAUTHORIZED, "Request does not implement required BASIC AUTH".to_string()).expect("Implement Logging"))
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.expect("Implement Logging | Failed to install Ctrl+C handler");
+}
+
+/// Accepts connections by hand, as the TLS handshake has to complete before hyper takes over and the client
+/// certificate has to be passed on to the request handler. Returns once `accepting` flips and every open connection has
+/// finished its current request.
+async fn serve_tls(addr: SocketAddr, tls: ReloadingTlsAcceptor, sender: Arc<Sender<Message>>, mut accepting: watch::Receiver<bool>) {
+    let listener: TcpListener = TcpListener::bind(addr).await.expect("Implement Logging | Failed to bind address");
+    let mut connections: JoinSet<()> = JoinSet::new();
+
+    loop {
+        let (stream, remote_addr) = tokio::select! {
+            connection = listener.accept() => match connection {
+                Ok(connection) => connection,
+                Err(err) => {
+                    eprintln!("server error: {err}");
+                    continue;
+                }
+            },
+            // Reap finished connections, so the set doesn't grow forever
+            Some(_) = connections.join_next() => continue,
+            _ = stopped(&mut accepting) => break
+        };
+
+        let acceptor = tls.acceptor();
+        let sender: Arc<Sender<Message>> = sender.clone();
+        let mut accepting: watch::Receiver<bool> = accepting.clone();
+
+        connections.spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => return eprintln!("TLS handshake failed: {err}")
+            };
+
+            let client_common_name: Option<Arc<str>> = client_common_name(&stream).map(Arc::from);
+
+            let service = service_fn(move |req: Request<Body>| {
+                handle_request(req, sender.clone(), client_common_name.clone(), remote_addr.ip())
+            });
+
+            let connection = Http::new().serve_connection(stream, service);
+            tokio::pin!(connection);
+
+            let result = tokio::select! {
+                result = connection.as_mut() => result,
+                _ = stopped(&mut accepting) => {
+                    // Lets the current request finish, then closes the connection
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+
+            if let Err(err) = result {
+                eprintln!("server error: {err}");
+            }
+        });
+    }
+
+    while connections.join_next().await.is_some() {}
+}
+
+/// Resolves once the server stops accepting connections
+async fn stopped(accepting: &mut watch::Receiver<bool>) {
+    // Only fails if the sender is gone, which also means shutting down
+    let _ = accepting.wait_for(|stopped| *stopped).await;
+}
+
+async fn handle_request(mut req: Request<Body>, sender: Arc<Sender<Message>>, client_common_name: Option<Arc<str>>, remote_ip: IpAddr) -> Result<Response<Body>, Infallible> {
+    match auth(&mut req, client_common_name.as_deref(), remote_ip).await {
+        // Now you have access to the sender
+        Ok(Some(identity)) => Ok(serve_request(req, sender, &identity).await),
+        Err(Error::LockedOut(retry_after)) => Ok(Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(RETRY_AFTER, retry_after.max(1))
+            .body(Body::from("Too many failed authentication attempts, try again later"))
+            .expect("Implement Logging")),
+        Err(Error::StaleSignature) => Ok(build_response(StatusCode::UNAUTHORIZED, "Signature timestamp is too far from the server time".to_string()).expect("Implement Logging")),
+        Err(Error::ReplayedSignature) => Ok(build_response(StatusCode::UNAUTHORIZED, "Nonce has already been used".to_string()).expect("Implement Logging")),
+        Err(Error::NonceCacheFull) => Ok(build_response(StatusCode::SERVICE_UNAVAILABLE, "Too many signed requests, try again later".to_string()).expect("Implement Logging")),
+        Err(Error::PayloadTooLarge) => Ok(payload_too_large()),
+        Err(Error::AuthentificationMissing) => Ok(build_response(StatusCode::UNAUTHORIZED, "Request requires BASIC AUTH, a Bearer token or an X-Api-Key header".to_string()).expect("Implement Logging")),
+        _ => Ok(build_response(StatusCode::UNAUTHORIZED, "Password and/or Username don't match".to_string()).expect("Implement Logging"))
     }
 }
@@ -1,68 +1,91 @@
-use std::collections::HashMap;
-use std::fs::read_to_string;
-use std::io;
-use std::process::Command;
-use std::str::Split;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::process::{Command, Output};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use postgres::{Client, NoTls};
 
-pub fn load_authentification_data(path: &str) -> HashMap<String, String> {
-    let env_file: String = read_to_string(path).expect("Implement Logging | .env file doesn't exist");
+use crate::error::Error;
+use crate::config::{DatabaseConfig, WorkerConfig};
 
-    let mut hash_map: HashMap<String, String> = env_file.lines().map(|line| {
-        let mut line: Split<&str> = line.split(" ");
+/// Seconds since the unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs())
+}
 
-        let user: &str = line.next().unwrap();
-        let password: &str = line.next().unwrap();
+/// Writes a temporary file next to `path` first and renames it over `path`, so a crash or a failed write never leaves a
+/// truncated file behind. The permissions of the file replaced are kept.
+pub fn write_atomically(path: &str, content: &str) -> io::Result<()> {
+    let temporary: String = format!("{path}.tmp");
+    let mut file: File = File::create(&temporary)?;
 
-        (user.to_string(), password.to_string())
-    }).collect::<HashMap<String, String>>();
+    if let Ok(metadata) = fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+    }
 
-    hash_map.shrink_to_fit();
+    file.write_all(content.as_bytes())?;
+    // Otherwise the rename may reach the disk before the content does
+    file.sync_all()?;
 
-    hash_map
+    fs::rename(&temporary, path)
 }
 
+/// Connects to the database, retrying with exponential back-off while it isn't reachable yet, e.g. because it is still
+/// starting up next to the server
+pub fn init_db(database: &DatabaseConfig, worker: &WorkerConfig) -> Result<Client, Error> {
+    // Validated at startup
+    let config: postgres::Config = database.url.parse().map_err(|err: postgres::Error| Error::DatabaseConfigError(err.to_string()))?;
 
-pub fn init_db() -> Result<Client, io::Error> {
-    // Start the PostgreSQL server
-    Command::new("pg_ctl")
-        .args(["-D", "/opt/homebrew/var/postgresql@14", "start"])
-        .output()?;
-
-    // Wait and retry logic with logging
-    let mut attempts = 0;
-    let max_attempts = 10;
+    let mut attempts: u32 = 0;
+    let mut retry_interval: Duration = Duration::from_secs(worker.connect_retry_interval);
+    let max_retry_interval: Duration = Duration::from_secs(worker.connect_max_retry_interval);
 
     loop {
         attempts += 1;
 
-        // Attempt to connect to the PostgreSQL server
-        match Client::configure()
-            .user("test_user")
-            .password("test_password")
-            .host("localhost")
-            .port(5432)
-            .dbname("test_db").connect(NoTls) {
+        match config.connect(NoTls) {
             Ok(client) => {
                 println!("Successfully connected to the database after {} attempts.", attempts);
                 return Ok(client);
             }
-            Err(err) if attempts < max_attempts => {
-                println!("Attempt {}: Failed to connect to the database. Error: {}", attempts, err);
-                sleep(Duration::from_secs(1)); // Wait for 1 second before retrying
+            Err(err) if attempts < worker.connect_attempts => {
+                println!("Attempt {}: Failed to connect to the database, retrying in {}s. Error: {}", attempts, retry_interval.as_secs(), err);
+                sleep(retry_interval);
+                retry_interval = (retry_interval * 2).min(max_retry_interval);
             }
             Err(err) => {
-                println!("Failed to connect to the database after {} attempts. Error: {}",attempts, err);
-                return Err(io::Error::new(io::ErrorKind::Other, err.to_string()));
+                return Err(Error::DatabaseConnectionError(format!("Failed to connect to the database after {attempts} attempts: {err}")));
             }
         }
     }
 }
 
-pub fn terminate_db() -> Result<(), io::Error> {
-    Command::new("pg_ctl").args(["-D", "/opt/homebrew/var/postgresql@14 stop"]).output()?;
-    Ok(())
+/// Starts the local PostgreSQL cluster in `database.data_directory`. Only used in embedded mode, otherwise the database
+/// is managed outside of the server.
+pub fn start_embedded_db(database: &DatabaseConfig) -> Result<(), Error> {
+    // A cluster that is already running is fine, pg_ctl status exits with 0 in that case
+    if pg_ctl(database, "status").is_ok() {
+        return Ok(());
+    }
+
+    pg_ctl(database, "start")
+}
+
+/// Stops the cluster started by [`start_embedded_db`]
+pub fn stop_embedded_db(database: &DatabaseConfig) -> Result<(), Error> {
+    pg_ctl(database, "stop")
+}
+
+fn pg_ctl(database: &DatabaseConfig, action: &str) -> Result<(), Error> {
+    let output: Output = Command::new(&database.pg_ctl)
+        .args(["-D", &database.data_directory, "-w", action])
+        .output()
+        .map_err(|err| Error::DatabaseConnectionError(format!("{}: {err}", database.pg_ctl)))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::DatabaseConnectionError(format!("pg_ctl {action} failed: {}", String::from_utf8_lossy(&output.stderr).trim())))
+    }
 }
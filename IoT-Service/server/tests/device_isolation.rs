@@ -0,0 +1,193 @@
+//! Regression tests for GET, PUT and DELETE reaching into the rows of other devices. The hand-written conditions these
+//! replaced read `device_id = $1 AND $2 < time_stamp OR $2 = time_stamp`, which matched every device at the boundary.
+//!
+//! The database tests need a PostgreSQL server, given as connection string by `IOT_TEST_DATABASE_URL`, so they are
+//! ignored by default and run with `cargo test -- --ignored`. Every test works in a schema of its own, which is dropped
+//! afterwards.
+
+use chrono::DateTime;
+use postgres::{Client, NoTls};
+use serde_json::{json, Value};
+
+use server::error::Error;
+use server::data_managment::database::{delete, get, insert, update};
+use server::data_managment::dataformat::{DataFrame, Query};
+use server::data_managment::filter::Filter;
+use server::data_managment::migrations::migrate;
+use server::data_managment::schema::Schemas;
+use server::data_managment::timestamp::Timestamp;
+
+use std::env;
+
+
+const DEVICE: i32 = 1;
+const OTHER_DEVICE: i32 = 2;
+
+/// Both devices report at these seconds since the epoch, so every time condition hits a boundary of the other device
+const SECONDS: [i64; 3] = [10, 20, 30];
+
+struct TestDatabase {
+    client: Client,
+    schema: String
+}
+
+impl TestDatabase {
+    fn connect(test: &str) -> Self {
+        let url: String = env::var("IOT_TEST_DATABASE_URL").expect("IOT_TEST_DATABASE_URL has to be set for the database tests");
+
+        let mut client: Client = Client::connect(&url, NoTls).expect("Failed to connect to IOT_TEST_DATABASE_URL");
+        let schema: String = format!("device_isolation_{test}_{}", std::process::id());
+
+        client.batch_execute(&format!("DROP SCHEMA IF EXISTS {schema} CASCADE; CREATE SCHEMA {schema}; SET search_path TO {schema}")).unwrap();
+        migrate(&mut client).unwrap();
+
+        let dataframes: Vec<DataFrame> = [DEVICE, OTHER_DEVICE].into_iter()
+            .flat_map(|device_id| SECONDS.map(|seconds| dataframe(device_id, seconds, 0)))
+            .collect();
+        insert(&dataframes, &mut client).unwrap();
+
+        Self { client, schema }
+    }
+
+    /// Every dataframe of the device as `(seconds, temp)`, ordered by time
+    fn rows(&mut self, device_id: i32) -> Vec<(i64, i64)> {
+        let filter: Filter = Filter::from_query(&Query { device_id: vec![device_id], order: Some("time_stamp".to_string()), ..Query::default() }, &Schemas::default()).unwrap();
+
+        get(&filter, &mut self.client).unwrap()
+            .into_iter()
+            .map(|dataframe| (dataframe.time_stamp.timestamp(), dataframe.fields["temp"].as_i64().unwrap()))
+            .collect()
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        let _ = self.client.batch_execute(&format!("DROP SCHEMA IF EXISTS {} CASCADE", self.schema));
+    }
+}
+
+fn at(seconds: i64) -> Timestamp {
+    DateTime::from_timestamp(seconds, 0).unwrap()
+}
+
+fn dataframe(device_id: i32, seconds: i64, temp: i64) -> DataFrame {
+    let Value::Object(fields) = json!({ "temp": temp, "rpm": 0 }) else {
+        unreachable!()
+    };
+
+    DataFrame { device_id, time_stamp: at(seconds), time_stamp_supplied: true, fields, ..DataFrame::default() }
+}
+
+/// Every shape of query the old conditions got wrong, restricted to [`DEVICE`] and matching its row at 20s
+fn boundary_queries() -> Vec<Query> {
+    let query = || Query { device_id: vec![DEVICE], ..Query::default() };
+
+    vec![
+        Query { time_start: Some(at(20)), time_end: Some(at(20)), ..query() },
+        Query { time_start: Some(at(30)), ..query() },
+        Query { time_end: Some(at(10)), ..query() },
+        Query { time_now: Some(at(20)), ..query() },
+        Query { filter: Some("time_stamp = 20 or temp = 0".to_string()), ..query() },
+        Query { filter: Some("not device_id = 1 or time_stamp = 20".to_string()), ..query() }
+    ]
+}
+
+fn write_sql(filter: &Filter) -> String {
+    let mut statement: String = String::new();
+    filter.write_sql(&mut statement, &mut Vec::new());
+    statement
+}
+
+#[test]
+fn device_condition_is_anded_with_the_filter() {
+    let query: Query = Query { device_id: vec![DEVICE, OTHER_DEVICE], filter: Some("temp > 1 or rpm < 1".to_string()), ..Query::default() };
+    let filter: Filter = Filter::from_query(&query, &Schemas::default()).unwrap();
+
+    assert_eq!(write_sql(&filter), " WHERE ((device_id IN ($1, $2)) AND (((fields -> $3::text) > $4::jsonb) OR ((fields -> $5::text) < $6::jsonb)))");
+}
+
+#[test]
+fn time_conditions_are_grouped() {
+    let query: Query = Query { device_id: vec![DEVICE], time_start: Some(at(10)), time_end: Some(at(20)), ..Query::default() };
+    let filter: Filter = Filter::from_query(&query, &Schemas::default()).unwrap();
+
+    assert_eq!(write_sql(&filter), " WHERE ((device_id = $1) AND (time_stamp >= $2) AND (time_stamp <= $3))");
+}
+
+#[test]
+fn modification_requires_device_and_condition() {
+    let schemas: Schemas = Schemas::default();
+
+    let without_device: Query = Query { time_start: Some(at(10)), ..Query::default() };
+    let without_condition: Query = Query { device_id: vec![DEVICE], ..Query::default() };
+    let with_limit: Query = Query { device_id: vec![DEVICE], time_start: Some(at(10)), limit: Some(1), ..Query::default() };
+
+    assert!(matches!(Filter::for_modification(&without_device, &schemas), Err(Error::InvalidFilter(_))));
+    assert!(matches!(Filter::for_modification(&without_condition, &schemas), Err(Error::InvalidFilter(_))));
+    assert!(matches!(Filter::for_modification(&with_limit, &schemas), Err(Error::InvalidFilter(_))));
+}
+
+#[test]
+#[ignore = "requires IOT_TEST_DATABASE_URL"]
+fn get_never_returns_other_devices() {
+    let mut database: TestDatabase = TestDatabase::connect("get");
+
+    for query in boundary_queries() {
+        let filter: Filter = Filter::from_query(&query, &Schemas::default()).unwrap();
+        let dataframes: Vec<DataFrame> = get(&filter, &mut database.client).unwrap();
+
+        assert!(!dataframes.is_empty(), "{query:?} matched nothing");
+        assert!(dataframes.iter().all(|dataframe| dataframe.device_id == DEVICE), "{query:?} returned another device");
+    }
+}
+
+#[test]
+#[ignore = "requires IOT_TEST_DATABASE_URL"]
+fn delete_never_touches_other_devices() {
+    let mut database: TestDatabase = TestDatabase::connect("delete");
+
+    for query in boundary_queries() {
+        let filter: Filter = Filter::for_modification(&query, &Schemas::default()).unwrap();
+        delete(&filter, &mut database.client).unwrap();
+
+        assert_eq!(database.rows(OTHER_DEVICE), SECONDS.map(|seconds| (seconds, 0)), "{query:?} deleted from another device");
+    }
+}
+
+#[test]
+#[ignore = "requires IOT_TEST_DATABASE_URL"]
+fn delete_removes_only_the_matching_rows() {
+    let mut database: TestDatabase = TestDatabase::connect("delete_matching");
+
+    let query: Query = Query { device_id: vec![DEVICE], time_start: Some(at(20)), ..Query::default() };
+    let deleted: u64 = delete(&Filter::for_modification(&query, &Schemas::default()).unwrap(), &mut database.client).unwrap();
+
+    assert_eq!(deleted, 2);
+    assert_eq!(database.rows(DEVICE), vec![(10, 0)]);
+}
+
+#[test]
+#[ignore = "requires IOT_TEST_DATABASE_URL"]
+fn update_never_touches_other_devices() {
+    let mut database: TestDatabase = TestDatabase::connect("update");
+
+    for (temp, query) in (1..).zip(boundary_queries()) {
+        let filter: Filter = Filter::for_modification(&query, &Schemas::default()).unwrap();
+        let updated: u64 = update(&filter, &dataframe(DEVICE, 20, temp), &mut database.client).unwrap();
+
+        assert!(updated > 0, "{query:?} updated nothing");
+        assert_eq!(database.rows(OTHER_DEVICE), SECONDS.map(|seconds| (seconds, 0)), "{query:?} updated another device");
+    }
+}
+
+#[test]
+#[ignore = "requires IOT_TEST_DATABASE_URL"]
+fn update_changes_only_the_matching_rows() {
+    let mut database: TestDatabase = TestDatabase::connect("update_matching");
+
+    let query: Query = Query { device_id: vec![DEVICE], time_now: Some(at(20)), ..Query::default() };
+    let updated: u64 = update(&Filter::for_modification(&query, &Schemas::default()).unwrap(), &dataframe(DEVICE, 20, 7), &mut database.client).unwrap();
+
+    assert_eq!(updated, 1);
+    assert_eq!(database.rows(DEVICE), vec![(10, 0), (20, 7), (30, 0)]);
+}